@@ -0,0 +1,155 @@
+//! Extraction benchmarks. Run `cargo bench --features rayon` to include the
+//! parallel variants.
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use opencv::{
+	core::{self, Mat, Scalar},
+	imgcodecs,
+	prelude::*,
+};
+use std::hint::black_box;
+use std::path::{Path, PathBuf};
+
+use gradient_generator::{
+	GradientConfig, GradientExtractor, ResizeStrategy, extract_gradient_from_mat_with_config,
+	extract_gradient_hex_batch, extract_gradient_hex_with_config,
+};
+
+/// Diagonal BGR ramp from dark blue at the top-left to orange at the
+/// bottom-right.
+fn ramp(width: i32, height: i32) -> Mat {
+	let mut image =
+		Mat::new_rows_cols_with_default(height, width, core::CV_8UC3, Scalar::all(0.0)).unwrap();
+	let span = (width + height - 2).max(1) as f64;
+	for y in 0..height {
+		let row = image.at_row_mut::<core::Vec3b>(y).unwrap();
+		for (x, pixel) in row.iter_mut().enumerate() {
+			let f = (x as f64 + y as f64) / span;
+			*pixel = core::Vec3b::from([
+				(200.0 - 160.0 * f) as u8,
+				(60.0 + 100.0 * f) as u8,
+				(40.0 + 215.0 * f) as u8,
+			]);
+		}
+	}
+	image
+}
+
+/// Writes `count` copies of `ramp(width, height)` as PNGs to a directory
+/// under the system temp dir, once, and returns their paths.
+fn ramp_files(count: usize, width: i32, height: i32) -> Vec<PathBuf> {
+	let dir = std::env::temp_dir().join("gradient-generator-bench");
+	std::fs::create_dir_all(&dir).unwrap();
+	let image = ramp(width, height);
+	(0..count)
+		.map(|i| {
+			let path = dir.join(format!("ramp-{}x{}-{}.png", width, height, i));
+			if !path.exists() {
+				imgcodecs::imwrite(path.to_str().unwrap(), &image, &core::Vector::new()).unwrap();
+			}
+			path
+		})
+		.collect()
+}
+
+/// 64 Full HD files, one after the other and, with `rayon`, spread over all
+/// cores.
+fn batch(c: &mut Criterion) {
+	let files = ramp_files(64, 1920, 1080);
+	let paths: Vec<&Path> = files.iter().map(PathBuf::as_path).collect();
+
+	let mut group = c.benchmark_group("batch");
+	group.sample_size(10);
+	group.bench_function("serial", |b| {
+		b.iter(|| extract_gradient_hex_batch(black_box(&paths)))
+	});
+	#[cfg(feature = "rayon")]
+	group.bench_function("par", |b| {
+		let config = GradientConfig::default();
+		b.iter(|| gradient_generator::extract_gradient_hex_batch_par(black_box(&paths), &config))
+	});
+	group.finish();
+}
+
+/// A loop over same-sized images, with a fresh set of buffers per call and
+/// with one `GradientExtractor` keeping them.
+fn reuse(c: &mut Criterion) {
+	let files = ramp_files(16, 1280, 720);
+	let config = GradientConfig::default();
+
+	let mut group = c.benchmark_group("reuse");
+	group.bench_function("fresh", |b| {
+		b.iter(|| {
+			for path in &files {
+				black_box(extract_gradient_hex_with_config(black_box(path), &config).unwrap());
+			}
+		})
+	});
+	group.bench_function("extractor", |b| {
+		let mut extractor = GradientExtractor::new(config.clone());
+		b.iter(|| {
+			for path in &files {
+				black_box(extractor.extract(black_box(path)).unwrap());
+			}
+		})
+	});
+	group.finish();
+}
+
+/// A 4K input on the CPU and through OpenCL. Without an OpenCL device both
+/// run on the CPU.
+fn opencl(c: &mut Criterion) {
+	let image = ramp(3840, 2160);
+
+	let mut group = c.benchmark_group("4k");
+	group.sample_size(20);
+	for use_opencl in [false, true] {
+		let config = GradientConfig {
+			use_opencl,
+			..GradientConfig::default()
+		};
+		let name = if use_opencl { "opencl" } else { "cpu" };
+		group.bench_function(name, |b| {
+			b.iter(|| extract_gradient_from_mat_with_config(black_box(&image), &config).unwrap())
+		});
+	}
+	group.finish();
+}
+
+/// The projection and direction loops at a width of 500, where they
+/// cover enough pixels to run in parallel with `rayon`. Compare runs with
+/// and without the feature.
+fn large_resize(c: &mut Criterion) {
+	let image = ramp(1920, 1080);
+	let config = GradientConfig {
+		resize_strategy: ResizeStrategy::FixedWidth(500),
+		..GradientConfig::default()
+	};
+
+	c.bench_function("width_500", |b| {
+		b.iter(|| extract_gradient_from_mat_with_config(black_box(&image), &config).unwrap())
+	});
+}
+
+/// Per-image cost of the `tests/fixtures` images over a range of
+/// resize widths, the main knob of the pipeline's cost.
+fn fixtures(c: &mut Criterion) {
+	let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+	let mut group = c.benchmark_group("fixtures");
+	for name in ["horizontal.png", "horizontal_dark.png", "solid.png"] {
+		let path = dir.join(name);
+		for width in [50, 100, 200, 400] {
+			let config = GradientConfig {
+				resize_strategy: ResizeStrategy::FixedWidth(width),
+				..GradientConfig::default()
+			};
+			group.bench_with_input(BenchmarkId::new(name, width), &path, |b, path| {
+				b.iter(|| extract_gradient_hex_with_config(black_box(path), &config).unwrap())
+			});
+		}
+	}
+	group.finish();
+}
+
+criterion_group!(benches, batch, reuse, opencl, large_resize, fixtures);
+criterion_main!(benches);
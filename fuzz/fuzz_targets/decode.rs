@@ -0,0 +1,10 @@
+#![no_main]
+
+use gradient_generator::{GradientConfig, extract_gradient_hex_from_bytes_with_config};
+use libfuzzer_sys::fuzz_target;
+
+// Arbitrary bytes must come back as `Ok` or `Err`; any panic or abort is a
+// bug in the decode path.
+fuzz_target!(|data: &[u8]| {
+	let _ = extract_gradient_hex_from_bytes_with_config(data, &GradientConfig::default());
+});
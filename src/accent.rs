@@ -0,0 +1,67 @@
+use opencv::{
+	core::{self, Mat, Scalar},
+	prelude::*,
+};
+use std::path::Path;
+
+use crate::output::chroma;
+use crate::pipeline::{
+	Analysis, Scratch, analyze, bgr_to_rgb, clusters, fit_residual, gradient_from_analysis,
+	read_image,
+};
+use crate::{GradientConfig, GradientResult, Result};
+
+/// Mean per-channel distance, in 8-bit levels, from the two-stop fit above
+/// which a pixel counts as not part of the gradient.
+const ACCENT_MIN_RESIDUAL: f64 = 30.0;
+/// Share of the pixels that has to be off the gradient before looking for
+/// an accent in them.
+const ACCENT_MIN_SHARE: f64 = 0.005;
+/// Clusters the off-gradient pixels are split into.
+const ACCENT_CLUSTERS: usize = 3;
+/// CIELAB chroma the most saturated cluster needs to count as an accent.
+const ACCENT_MIN_CHROMA: f64 = 20.0;
+
+pub fn extract_gradient_and_accent(image_path: &Path) -> Result<(GradientResult, Option<[u8; 3]>)> {
+	extract_gradient_and_accent_with_config(image_path, &GradientConfig::default())
+}
+
+/// Returns the gradient together with an accent color: the most saturated
+/// of a few k-means clusters of the pixels the two-stop fit explains
+/// poorly, such as a logo or highlight on top of the gradient. `None` when
+/// too few pixels are off the gradient or none of them is saturated.
+pub fn extract_gradient_and_accent_with_config(
+	image_path: &Path,
+	config: &GradientConfig,
+) -> Result<(GradientResult, Option<[u8; 3]>)> {
+	let img = read_image(image_path, config)?;
+	let analysis = analyze(&img, config, &mut Scratch::default())?;
+	let result = gradient_from_analysis(&analysis, config)?;
+	let accent = accent(&analysis, &result)?;
+	Ok((result, accent))
+}
+
+fn accent(analysis: &Analysis, result: &GradientResult) -> Result<Option<[u8; 3]>> {
+	let [start, end] = [result.start_rgb, result.end_rgb]
+		.map(|[r, g, b]| core::Vec3d::from([b as f64, g as f64, r as f64]));
+	let residual = fit_residual(analysis, start, end)?;
+
+	let mut off = Mat::default();
+	core::compare(
+		&residual,
+		&Scalar::all(ACCENT_MIN_RESIDUAL),
+		&mut off,
+		core::CMP_GT,
+	)?;
+	let off = analysis.restrict(off)?;
+	let count = core::count_non_zero(&off)?;
+	if (count as f64) < ACCENT_MIN_SHARE * off.total() as f64 {
+		return Ok(None);
+	}
+
+	let accent = clusters(&analysis.blurred, &off, ACCENT_CLUSTERS)?
+		.into_iter()
+		.map(|(center, _)| bgr_to_rgb(core::Vec3d::from(center.map(|c| c.round()))))
+		.max_by(|&a, &b| chroma(a).total_cmp(&chroma(b)));
+	Ok(accent.filter(|&rgb| chroma(rgb) >= ACCENT_MIN_CHROMA))
+}
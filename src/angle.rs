@@ -0,0 +1,362 @@
+use opencv::{
+	core::{self, BorderTypes, CV_8UC1, CV_64F, Mat},
+	imgproc,
+	prelude::*,
+};
+
+use crate::pipeline::{UMAT_USAGE, opencl_enabled, with_opencl};
+use crate::{EdgeOperator, GradientConfig, GradientError, Result, compass_angle};
+
+/// Fewer valid gradient pixels than this and the image is treated as flat.
+pub(crate) const MIN_VALID_PIXELS: i32 = 10;
+
+/// Magnitudes up to this are treated as no gradient at all.
+const MIN_MAGNITUDE: f64 = 1e-6;
+
+/// Coherence above which the angle is checked against the nearest axis.
+const AXIS_MIN_CONFIDENCE: f64 = 0.999;
+/// Degrees from an axis within which such an angle is snapped to it.
+const AXIS_TOLERANCE: f64 = 0.1;
+
+/// Bins of 10 degrees used to look for separate direction peaks.
+const MULTIMODAL_BINS: usize = 18;
+/// Peaks closer than this many bins count as the same direction.
+const MULTIMODAL_MIN_SEPARATION: usize = 3;
+/// Share of the valid pixels each peak and its neighbours need to hold.
+const MULTIMODAL_PEAK_SHARE: f64 = 0.2;
+/// Above this coherence the directions agree well enough to be one mode.
+const MULTIMODAL_MAX_CONFIDENCE: f64 = 0.7;
+
+pub(crate) struct DominantDirection {
+	pub(crate) angle: f64,
+	pub(crate) confidence: f64,
+}
+
+pub(crate) struct GradientField {
+	pub(crate) mag: Mat,
+	pub(crate) angle: Mat,
+	pub(crate) valid: Mat,
+	pub(crate) valid_count: i32,
+}
+
+/// Returns the dominant gradient direction of a single-channel image in
+/// degrees, measured clockwise from the top. Exactly axis-aligned images
+/// give exact multiples of 90, as in `GradientResult::angle`.
+pub fn estimate_gradient_angle(gray: &Mat) -> Result<f64> {
+	let field = gradient_field(
+		gray,
+		&GradientConfig::default(),
+		None,
+		&mut Mat::default(),
+		&mut Mat::default(),
+	)?;
+	Ok(axis_snapped_angle(&field_direction(&field)?))
+}
+
+/// Compass angle of `direction`. Exactly axis-aligned ramps pick up a few
+/// hundredths of a degree of noise in the derivatives; when the directions
+/// agree this closely the axis itself is the answer.
+pub(crate) fn axis_snapped_angle(direction: &DominantDirection) -> f64 {
+	let angle = compass_angle(direction.angle);
+	if direction.confidence >= AXIS_MIN_CONFIDENCE {
+		snap_angle(angle, 90.0, AXIS_TOLERANCE)
+	} else {
+		angle
+	}
+}
+
+/// `angle` moved to the nearest multiple of `step` when it is within
+/// `tolerance` degrees of it.
+pub(crate) fn snap_angle(angle: f64, step: f64, tolerance: f64) -> f64 {
+	if step <= 0.0 {
+		return angle;
+	}
+
+	let nearest = (angle / step).round() * step;
+	if (angle - nearest).abs() <= tolerance {
+		nearest.rem_euclid(360.0)
+	} else {
+		angle
+	}
+}
+
+/// Generic over `Mat` and `UMat`, like the derivatives.
+pub(crate) fn gray_image(
+	blurred: &impl core::ToInputArray,
+	gray: &mut impl core::ToOutputArray,
+) -> Result<()> {
+	imgproc::cvt_color(
+		blurred,
+		gray,
+		imgproc::COLOR_BGR2GRAY,
+		0,
+		core::AlgorithmHint::ALGO_HINT_DEFAULT,
+	)?;
+
+	Ok(())
+}
+
+/// Only the pixels set in `keep`, when given, can be valid, and only their
+/// magnitudes set a relative threshold. `grad_x` and `grad_y` receive the
+/// derivatives; passing Mats from an earlier call of the same size reuses
+/// their buffers.
+pub(crate) fn gradient_field(
+	gray: &Mat,
+	config: &GradientConfig,
+	keep: Option<&Mat>,
+	grad_x: &mut Mat,
+	grad_y: &mut Mat,
+) -> Result<GradientField> {
+	if gray.channels() != 1 {
+		return Err(GradientError::InvalidArgument(format!(
+			"Expected a single-channel image, got {} channels",
+			gray.channels()
+		)));
+	}
+
+	if opencl_enabled(config)? {
+		with_opencl(|| {
+			let src = gray.get_umat(core::AccessFlag::ACCESS_READ, UMAT_USAGE)?;
+			let mut grad_x_gpu = core::UMat::new(UMAT_USAGE);
+			let mut grad_y_gpu = core::UMat::new(UMAT_USAGE);
+			derivative(&src, config.edge_operator, 1, 0, &mut grad_x_gpu)?;
+			derivative(&src, config.edge_operator, 0, 1, &mut grad_y_gpu)?;
+			grad_x_gpu.copy_to(grad_x)?;
+			grad_y_gpu.copy_to(grad_y)?;
+			Ok(())
+		})?;
+	} else {
+		derivative(gray, config.edge_operator, 1, 0, grad_x)?;
+		derivative(gray, config.edge_operator, 0, 1, grad_y)?;
+	}
+
+	let mut mag = Mat::default();
+	let mut angle_rad = Mat::default();
+	core::cart_to_polar(grad_x, grad_y, &mut mag, &mut angle_rad, false)?;
+
+	// On a solid-color image every magnitude is zero or rounding noise; the
+	// floor keeps all of it out instead of letting a relative threshold of
+	// ~0 select arbitrary pixels, so the image comes out `Flat`.
+	let mut magnitudes = kept_magnitudes(&mag, keep)?;
+	#[cfg(feature = "tracing")]
+	let max_val = magnitudes.iter().copied().fold(0.0, f64::max);
+	let threshold = config
+		.magnitude_threshold
+		.threshold(&mut magnitudes)
+		.max(MIN_MAGNITUDE);
+
+	let mut valid_mask = Mat::default();
+	imgproc::threshold(
+		&mag,
+		&mut valid_mask,
+		threshold,
+		255.0,
+		imgproc::THRESH_BINARY,
+	)?;
+	let mut valid_mask_output = valid_mask.clone();
+	valid_mask.convert_to(&mut valid_mask_output, CV_8UC1, 1.0, 0.0)?;
+	if let Some(keep) = keep {
+		let mut kept = Mat::default();
+		core::bitwise_and(&valid_mask_output, keep, &mut kept, &core::no_array())?;
+		valid_mask_output = kept;
+	}
+
+	let valid_count = core::count_non_zero(&valid_mask_output)?;
+	#[cfg(feature = "tracing")]
+	tracing::debug!(max_val, threshold, valid_count, "gradient magnitude");
+
+	Ok(GradientField {
+		mag,
+		angle: angle_rad,
+		valid: valid_mask_output,
+		valid_count,
+	})
+}
+
+/// The magnitudes of the pixels set in `keep`, or all of them.
+fn kept_magnitudes(mag: &Mat, keep: Option<&Mat>) -> Result<Vec<f64>> {
+	let Some(keep) = keep else {
+		return Ok(mag.data_typed::<f64>()?.to_vec());
+	};
+
+	let mut kept = Vec::new();
+	for y in 0..mag.rows() {
+		let keep_row = keep.at_row::<u8>(y)?;
+		for (&k, &m) in keep_row.iter().zip(mag.at_row::<f64>(y)?) {
+			if k != 0 {
+				kept.push(m);
+			}
+		}
+	}
+	Ok(kept)
+}
+
+fn derivative(
+	gray: &impl core::ToInputArray,
+	operator: EdgeOperator,
+	dx: i32,
+	dy: i32,
+	grad: &mut impl core::ToOutputArray,
+) -> Result<()> {
+	match operator {
+		EdgeOperator::Sobel { ksize } => imgproc::sobel(
+			gray,
+			grad,
+			CV_64F,
+			dx,
+			dy,
+			ksize,
+			1.0,
+			0.0,
+			BorderTypes::BORDER_REFLECT_101 as i32,
+		)?,
+		EdgeOperator::Scharr => imgproc::scharr(
+			gray,
+			grad,
+			CV_64F,
+			dx,
+			dy,
+			1.0,
+			0.0,
+			BorderTypes::BORDER_REFLECT_101 as i32,
+		)?,
+	}
+
+	Ok(())
+}
+
+/// Magnitude-weighted sums of the cosine and sine of the doubled angles, and
+/// of the weights, over the valid pixels.
+fn direction_sums(field: &GradientField) -> Result<(f64, f64, f64)> {
+	let rows = field.angle.rows();
+
+	#[cfg(feature = "rayon")]
+	if rows as usize * field.angle.cols() as usize >= crate::PARALLEL_MIN_PIXELS {
+		use rayon::prelude::*;
+
+		// The rows are summed in parallel but added up in row order, as
+		// below; a parallel reduce would group them by how the work was
+		// split and change the last bits between runs.
+		let per_row = (0..rows)
+			.into_par_iter()
+			.map(|y| row_sums(field, y))
+			.collect::<Result<Vec<_>>>()?;
+		return Ok(per_row.into_iter().fold((0.0, 0.0, 0.0), add_sums));
+	}
+
+	let mut sums = (0.0, 0.0, 0.0);
+	for y in 0..rows {
+		sums = add_sums(sums, row_sums(field, y)?);
+	}
+	Ok(sums)
+}
+
+fn add_sums(a: (f64, f64, f64), b: (f64, f64, f64)) -> (f64, f64, f64) {
+	(a.0 + b.0, a.1 + b.1, a.2 + b.2)
+}
+
+/// Rows are fetched one at a time so the loop honours the row stride of
+/// non-continuous Mats. Each pixel is weighted by its magnitude so strong
+/// edges outvote barely-above-threshold noise.
+fn row_sums(field: &GradientField, y: i32) -> Result<(f64, f64, f64)> {
+	let mask_row = field.valid.at_row::<u8>(y)?;
+	let angle_row = field.angle.at_row::<f64>(y)?;
+	let mag_row = field.mag.at_row::<f64>(y)?;
+
+	let mut sums = (0.0, 0.0, 0.0);
+	for ((&m, &a), &weight) in mask_row.iter().zip(angle_row).zip(mag_row) {
+		if m != 0 {
+			let double_angle = 2.0 * a;
+			sums.0 += weight * double_angle.cos();
+			sums.1 += weight * double_angle.sin();
+			sums.2 += weight;
+		}
+	}
+	Ok(sums)
+}
+
+pub(crate) fn field_direction(field: &GradientField) -> Result<DominantDirection> {
+	let direction = if field.valid_count < MIN_VALID_PIXELS {
+		DominantDirection {
+			angle: 0.0,
+			confidence: 0.0,
+		}
+	} else {
+		let (sum_cos, sum_sin, sum_weight) = direction_sums(field)?;
+
+		if sum_weight == 0.0 {
+			DominantDirection {
+				angle: 0.0,
+				confidence: 0.0,
+			}
+		} else {
+			let avg_cos = sum_cos / sum_weight;
+			let avg_sin = sum_sin / sum_weight;
+			DominantDirection {
+				angle: 0.5 * avg_sin.atan2(avg_cos),
+				confidence: avg_cos.hypot(avg_sin).clamp(0.0, 1.0),
+			}
+		}
+	};
+	#[cfg(feature = "tracing")]
+	tracing::trace!(
+		dominant_angle = direction.angle,
+		confidence = direction.confidence,
+		"dominant direction"
+	);
+
+	Ok(direction)
+}
+
+/// Counts the valid pixels per direction, folded to `[0, 180)` compass
+/// degrees and split into `bins` equal buckets.
+pub(crate) fn angle_histogram(field: &GradientField, bins: usize) -> Result<Vec<u32>> {
+	if bins == 0 {
+		return Err(GradientError::InvalidArgument(
+			"At least one bin is required".to_string(),
+		));
+	}
+
+	let mut counts = vec![0; bins];
+	for y in 0..field.angle.rows() {
+		let mask_row = field.valid.at_row::<u8>(y)?;
+		let angle_row = field.angle.at_row::<f64>(y)?;
+		for (&m, &a) in mask_row.iter().zip(angle_row) {
+			if m != 0 {
+				let folded = compass_angle(a).rem_euclid(180.0);
+				let bin = ((folded / 180.0 * bins as f64) as usize).min(bins - 1);
+				counts[bin] += 1;
+			}
+		}
+	}
+
+	Ok(counts)
+}
+
+/// Whether the directions form two strong, well separated peaks that the
+/// doubled-angle mean averages into a meaningless middle value.
+pub(crate) fn is_multimodal(field: &GradientField, direction: &DominantDirection) -> Result<bool> {
+	if field.valid_count < MIN_VALID_PIXELS || direction.confidence > MULTIMODAL_MAX_CONFIDENCE {
+		return Ok(false);
+	}
+
+	let counts = angle_histogram(field, MULTIMODAL_BINS)?;
+	let bins = counts.len();
+	let distance = |a: usize, b: usize| {
+		let d = a.abs_diff(b);
+		d.min(bins - d)
+	};
+	let peak_mass =
+		|peak: usize| counts[(peak + bins - 1) % bins] + counts[peak] + counts[(peak + 1) % bins];
+
+	let first = (0..bins).max_by_key(|&i| counts[i]).unwrap_or(0);
+	let Some(second) = (0..bins)
+		.filter(|&i| distance(i, first) >= MULTIMODAL_MIN_SEPARATION)
+		.max_by_key(|&i| counts[i])
+	else {
+		return Ok(false);
+	};
+
+	let min_mass = MULTIMODAL_PEAK_SHARE * field.valid_count as f64;
+	Ok(peak_mass(first) as f64 >= min_mass && peak_mass(second) as f64 >= min_mass)
+}
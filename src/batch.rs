@@ -0,0 +1,73 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{GradientConfig, GradientResult, Result, extract_gradient_hex_with_config};
+
+/// Extracts every file in `paths`, in order. A file that cannot be read or
+/// decoded gives an `Err` entry without stopping the others.
+pub fn extract_gradient_hex_batch(paths: &[&Path]) -> Vec<(PathBuf, Result<GradientResult>)> {
+	extract_gradient_hex_batch_with_config(paths, &GradientConfig::default())
+}
+
+pub fn extract_gradient_hex_batch_with_config(
+	paths: &[&Path],
+	config: &GradientConfig,
+) -> Vec<(PathBuf, Result<GradientResult>)> {
+	paths
+		.iter()
+		.map(|path| {
+			(
+				path.to_path_buf(),
+				extract_gradient_hex_with_config(path, config),
+			)
+		})
+		.collect()
+}
+
+/// Like `extract_gradient_hex_batch_with_config`, calling `progress(done, total)` after
+/// each file. Once `cancel` is set the remaining files are skipped and the
+/// results so far are returned.
+pub fn extract_gradient_hex_batch_with(
+	paths: &[&Path],
+	config: &GradientConfig,
+	mut progress: impl FnMut(usize, usize),
+	cancel: &AtomicBool,
+) -> Vec<(PathBuf, Result<GradientResult>)> {
+	let mut results = Vec::with_capacity(paths.len());
+	for path in paths {
+		if cancel.load(Ordering::Relaxed) {
+			break;
+		}
+		results.push((
+			path.to_path_buf(),
+			extract_gradient_hex_with_config(path, config),
+		));
+		progress(results.len(), paths.len());
+	}
+	results
+}
+
+/// Results are returned in the same order as `paths`.
+///
+/// Every call works on its own `Mat`s, and the only OpenCV state the
+/// pipeline changes, the OpenCL switch of `use_opencl`, is per thread and
+/// restored after use, so running them concurrently is safe. OpenCV
+/// parallelizes some of them internally as well; call
+/// `opencv::core::set_num_threads(1)` if that oversubscribes the CPU.
+#[cfg(feature = "rayon")]
+pub fn extract_gradient_hex_batch_par(
+	paths: &[&Path],
+	config: &GradientConfig,
+) -> Vec<(PathBuf, Result<GradientResult>)> {
+	use rayon::prelude::*;
+
+	paths
+		.par_iter()
+		.map(|path| {
+			(
+				path.to_path_buf(),
+				extract_gradient_hex_with_config(path, config),
+			)
+		})
+		.collect()
+}
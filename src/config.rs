@@ -0,0 +1,763 @@
+use crate::{GradientError, Result};
+
+#[derive(Debug, Clone)]
+pub struct GradientConfig {
+	pub resize_strategy: ResizeStrategy,
+	pub blur_sigma: f64,
+	pub magnitude_threshold: ThresholdMode,
+	/// Share of the projection range at each end whose pixels give the start
+	/// and end colors, in `(0, 0.5]`. Smaller values sample a narrower tail
+	/// and give crisper, more saturated end colors; a tiny value still keeps
+	/// the pixels at the exact extremes.
+	pub band_frac: f64,
+	pub average_space: ColorAverageSpace,
+	pub color_statistic: ColorStatistic,
+	pub stop_selection: StopSelection,
+	/// Average band colors in linear light instead of on the sRGB-encoded
+	/// bytes, which otherwise darkens the mix of light and dark pixels.
+	/// Ignored with `ColorAverageSpace::Lab`, whose conversion already
+	/// linearizes.
+	pub linearize: bool,
+	pub rounding: RoundingMode,
+	pub edge_operator: EdgeOperator,
+	/// Coherence a gradient needs to be reported as linear or radial rather
+	/// than flat, and for `try_extract_gradient` to return it at all.
+	pub min_coherence: f64,
+	/// How much the radial coherence has to exceed the linear one for the
+	/// gradient to be reported as radial.
+	pub radial_margin: f64,
+	/// When set, angles within this many degrees of a multiple of
+	/// `snap_step` are snapped to it.
+	pub snap_angle: Option<f64>,
+	pub snap_step: f64,
+	/// Reconstruct the two-stop gradient and report how far the image is from
+	/// it in `GradientResult::fit_error`.
+	pub compute_fit_error: bool,
+	/// Fraction of the width and height ignored on every side, for both the
+	/// angle and the colors. Keeps watermarks, rounded corners and vignettes
+	/// out of the estimate.
+	pub border_exclude_frac: f64,
+	/// Rotate and flip decoded images according to their EXIF orientation
+	/// tag, so phone photos are analyzed upright.
+	pub respect_exif: bool,
+	/// Run the resize, blur, grayscale conversion and derivatives through
+	/// OpenCL (`UMat`) when OpenCV reports it available, falling back to the
+	/// CPU otherwise. The pixel loops after them stay on the CPU. OpenCV's
+	/// per-thread OpenCL switch is restored after each step.
+	pub use_opencl: bool,
+	/// Keep 16-bit and float inputs at full precision through the blur and
+	/// the derivatives instead of converting them to 8 bits up front. Only
+	/// the sampled colors are quantized, to 8 and 16 bits.
+	pub high_bit_depth: bool,
+	/// Apply a gray-world white balance to the resized image before the
+	/// colors are sampled. The angle is estimated before balancing.
+	pub white_balance: bool,
+	/// Stretch the grayscale used for the angle to the full range, so very
+	/// faint gradients stand out over noise. The colors are unaffected.
+	pub normalize_contrast: bool,
+	/// Median-filter images that look dithered before anything else, so the
+	/// scattered two-color pixels of old palette images do not add noise to
+	/// the angle and the colors.
+	pub denoise_dither: bool,
+	pub orientation: Orientation,
+	/// Also estimate the angle on each color channel on its own and report
+	/// it in `GradientResult::per_channel_angles`. Experimental.
+	pub per_channel_angles: bool,
+	/// OpenCV interpolation flag (`imgproc::INTER_*`) used to resize the
+	/// input. `INTER_AREA` averages well when shrinking but degrades to
+	/// nearest-neighbour when enlarging, so it is replaced by
+	/// `INTER_LINEAR` when the target is larger than the input.
+	#[cfg(feature = "opencv")]
+	pub interpolation: i32,
+	/// Memory-map input files instead of reading them into a buffer, which
+	/// saves a copy of very large files. Falls back to reading when the file
+	/// cannot be mapped. The file must not be truncated while it is being
+	/// decoded, or the process may be killed with `SIGBUS`.
+	#[cfg(feature = "memmap")]
+	pub memory_map: bool,
+	/// Region of the input image to analyze, in input pixels. The rest of
+	/// the image is ignored.
+	#[cfg(feature = "opencv")]
+	pub roi: Option<opencv::core::Rect>,
+}
+
+/// Which side of the image is scaled to the given number of pixels before
+/// the analysis; the other follows the aspect ratio. The size is the main
+/// knob of the pipeline's cost. `FixedWidth` leaves tall images only
+/// a few pixels wide relative to their height; `LongestSide` keeps the same
+/// detail for portrait and landscape images. Images more than ten times
+/// taller than wide, down to single-pixel columns, resize by their longest
+/// side under either strategy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResizeStrategy {
+	FixedWidth(i32),
+	LongestSide(i32),
+}
+
+/// Height to width ratio above which `FixedWidth` would blow a narrow
+/// column up into a huge image, and `LongestSide` is used instead.
+const FIXED_WIDTH_MAX_ASPECT: f64 = 10.0;
+
+impl ResizeStrategy {
+	pub(crate) fn dim(self) -> i32 {
+		match self {
+			Self::FixedWidth(dim) | Self::LongestSide(dim) => dim,
+		}
+	}
+
+	/// Target width and height for a `width` x `height` image. The other
+	/// side is rounded in floating point and kept at least one pixel, so
+	/// panoramic strips resize to a thin image instead of to nothing. Thin
+	/// results have few valid gradient pixels and may come out flat.
+	pub(crate) fn target_size(self, width: f64, height: f64) -> (i32, i32) {
+		let dim = self.dim();
+		let scale = |side: f64, other: f64| ((dim as f64 * side / other).round() as i32).max(1);
+		let by_longest_side = match self {
+			Self::FixedWidth(_) => height > FIXED_WIDTH_MAX_ASPECT * width,
+			Self::LongestSide(_) => height > width,
+		};
+		if by_longest_side {
+			(scale(width, height), dim)
+		} else {
+			(dim, scale(height, width))
+		}
+	}
+}
+
+impl Default for ResizeStrategy {
+	fn default() -> Self {
+		Self::FixedWidth(100)
+	}
+}
+
+/// Color space the band colors are averaged in. Averaging in CIELAB keeps
+/// bands that span a hue range from turning muddy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorAverageSpace {
+	#[default]
+	Bgr,
+	Lab,
+}
+
+/// How the pixels of a band are reduced to one color. The per-channel
+/// median ignores overlaid text or logos that would pull the mean off;
+/// `TrimmedMean` drops `trim_frac` of the values from each tail of every
+/// channel before averaging, which is cheaper than a full median.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ColorStatistic {
+	#[default]
+	Mean,
+	Median,
+	TrimmedMean {
+		trim_frac: f64,
+	},
+}
+
+impl ColorStatistic {
+	pub(crate) fn validate(&self) -> Result<()> {
+		match *self {
+			Self::TrimmedMean { trim_frac } if !(0.0..0.5).contains(&trim_frac) => {
+				Err(GradientError::InvalidArgument(format!(
+					"trim_frac must be in [0, 0.5), got {}",
+					trim_frac
+				)))
+			}
+			_ => Ok(()),
+		}
+	}
+}
+
+/// How a band is turned into a color. `KMeans` clusters the band's pixels
+/// into `k` groups and takes the center of the largest one, which keeps
+/// stop colors crisp on slightly textured gradients. `Mean` reduces the band
+/// with `ColorStatistic`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StopSelection {
+	#[default]
+	Mean,
+	KMeans {
+		k: usize,
+	},
+}
+
+impl StopSelection {
+	pub(crate) fn validate(&self) -> Result<()> {
+		match *self {
+			Self::KMeans { k: 0 } => Err(GradientError::InvalidArgument(
+				"KMeans needs at least one cluster".to_string(),
+			)),
+			_ => Ok(()),
+		}
+	}
+}
+
+/// Which gradient magnitudes count towards the angle: those above a fraction
+/// of the strongest one, or above a percentile (0 to 100) of all of them. A
+/// percentile keeps a few very sharp edges from raising the bar for the
+/// actual gradient.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThresholdMode {
+	FracOfMax(f64),
+	Percentile(f64),
+}
+
+impl ThresholdMode {
+	pub(crate) fn validate(&self) -> Result<()> {
+		match *self {
+			Self::FracOfMax(frac) if !(0.0..=1.0).contains(&frac) => {
+				Err(GradientError::InvalidArgument(format!(
+					"FracOfMax must be in [0, 1], got {}",
+					frac
+				)))
+			}
+			Self::Percentile(percentile) if !(0.0..=100.0).contains(&percentile) => {
+				Err(GradientError::InvalidArgument(format!(
+					"Percentile must be in [0, 100], got {}",
+					percentile
+				)))
+			}
+			_ => Ok(()),
+		}
+	}
+
+	/// Magnitude a pixel has to exceed. Reorders `magnitudes`.
+	pub(crate) fn threshold(self, magnitudes: &mut [f64]) -> f64 {
+		match self {
+			Self::FracOfMax(frac) => frac * magnitudes.iter().copied().fold(0.0, f64::max),
+			Self::Percentile(_) if magnitudes.is_empty() => 0.0,
+			Self::Percentile(percentile) => {
+				let rank = (percentile / 100.0 * (magnitudes.len() - 1) as f64).round() as usize;
+				*magnitudes.select_nth_unstable_by(rank, f64::total_cmp).1
+			}
+		}
+	}
+}
+
+impl Default for ThresholdMode {
+	fn default() -> Self {
+		Self::FracOfMax(0.1)
+	}
+}
+
+/// Which end of the gradient axis is reported as the start. `Raw` takes the
+/// end the estimated direction happens to point away from, which can flip
+/// between near-vertical images that look the same. `TopLeftStart` always
+/// starts at the end towards the top-left, so the gradient runs towards
+/// the bottom-right half of the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Orientation {
+	#[default]
+	Raw,
+	TopLeftStart,
+}
+
+/// How averaged channel values are turned into whole 8-bit levels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RoundingMode {
+	#[default]
+	Round,
+	Floor,
+	Ceil,
+}
+
+impl RoundingMode {
+	pub(crate) fn apply(self, value: f64) -> f64 {
+		match self {
+			Self::Round => value.round(),
+			Self::Floor => value.floor(),
+			Self::Ceil => value.ceil(),
+		}
+	}
+}
+
+impl Default for GradientConfig {
+	fn default() -> Self {
+		Self {
+			resize_strategy: ResizeStrategy::default(),
+			blur_sigma: 15.0,
+			magnitude_threshold: ThresholdMode::default(),
+			band_frac: 0.15,
+			average_space: ColorAverageSpace::Bgr,
+			color_statistic: ColorStatistic::Mean,
+			stop_selection: StopSelection::Mean,
+			linearize: false,
+			rounding: RoundingMode::Round,
+			edge_operator: EdgeOperator::default(),
+			min_coherence: 0.3,
+			radial_margin: 0.05,
+			snap_angle: None,
+			snap_step: 45.0,
+			compute_fit_error: false,
+			border_exclude_frac: 0.0,
+			respect_exif: true,
+			use_opencl: false,
+			high_bit_depth: false,
+			white_balance: false,
+			normalize_contrast: false,
+			denoise_dither: false,
+			orientation: Orientation::Raw,
+			per_channel_angles: false,
+			#[cfg(feature = "opencv")]
+			interpolation: opencv::imgproc::INTER_AREA,
+			#[cfg(feature = "memmap")]
+			memory_map: false,
+			#[cfg(feature = "opencv")]
+			roi: None,
+		}
+	}
+}
+
+impl GradientConfig {
+	pub fn builder() -> GradientConfigBuilder {
+		GradientConfigBuilder::default()
+	}
+
+	/// Checks every field, returning `GradientError::InvalidArgument` naming
+	/// the first one out of range. Called by `GradientConfigBuilder::build`
+	/// and at the start of every extraction, so a struct-literal config
+	/// fails with the same message instead of an OpenCV assertion.
+	pub fn validate(&self) -> Result<()> {
+		let kernel = self.edge_operator.kernel_size();
+		if self.resize_strategy.dim() < kernel {
+			return Err(GradientError::InvalidArgument(format!(
+				"resize_strategy size must be at least the {} pixel derivative kernel, got {}",
+				kernel,
+				self.resize_strategy.dim()
+			)));
+		}
+		if !self.blur_sigma.is_finite() || self.blur_sigma < 0.0 {
+			return Err(GradientError::InvalidArgument(format!(
+				"blur_sigma must be finite and not negative, got {}",
+				self.blur_sigma
+			)));
+		}
+		if !(self.band_frac > 0.0 && self.band_frac <= 0.5) {
+			return Err(GradientError::InvalidArgument(format!(
+				"band_frac must be in (0, 0.5], got {}",
+				self.band_frac
+			)));
+		}
+		if !(0.0..0.5).contains(&self.border_exclude_frac) {
+			return Err(GradientError::InvalidArgument(format!(
+				"border_exclude_frac must be in [0, 0.5), got {}",
+				self.border_exclude_frac
+			)));
+		}
+		if !(0.0..=1.0).contains(&self.min_coherence) {
+			return Err(GradientError::InvalidArgument(format!(
+				"min_coherence must be in [0, 1], got {}",
+				self.min_coherence
+			)));
+		}
+		if self.radial_margin.is_nan() || self.radial_margin < 0.0 {
+			return Err(GradientError::InvalidArgument(format!(
+				"radial_margin must not be negative, got {}",
+				self.radial_margin
+			)));
+		}
+		if let Some(tolerance) = self.snap_angle
+			&& (tolerance.is_nan() || tolerance < 0.0)
+		{
+			return Err(GradientError::InvalidArgument(format!(
+				"snap_angle must not be negative, got {}",
+				tolerance
+			)));
+		}
+		if !self.snap_step.is_finite() || self.snap_step <= 0.0 {
+			return Err(GradientError::InvalidArgument(format!(
+				"snap_step must be finite and positive, got {}",
+				self.snap_step
+			)));
+		}
+		#[cfg(feature = "opencv")]
+		{
+			use opencv::imgproc;
+			if ![
+				imgproc::INTER_NEAREST,
+				imgproc::INTER_LINEAR,
+				imgproc::INTER_CUBIC,
+				imgproc::INTER_AREA,
+				imgproc::INTER_LANCZOS4,
+				imgproc::INTER_LINEAR_EXACT,
+				imgproc::INTER_NEAREST_EXACT,
+			]
+			.contains(&self.interpolation)
+			{
+				return Err(GradientError::InvalidArgument(format!(
+					"interpolation must be an imgproc::INTER_* resize flag, got {}",
+					self.interpolation
+				)));
+			}
+		}
+		self.magnitude_threshold.validate()?;
+		self.edge_operator.validate()?;
+		self.color_statistic.validate()?;
+		self.stop_selection.validate()?;
+
+		Ok(())
+	}
+}
+
+/// Chainable alternative to struct-literal initialization. Fields that are
+/// not set keep their `GradientConfig::default()` value.
+#[derive(Debug, Clone, Default)]
+pub struct GradientConfigBuilder {
+	config: GradientConfig,
+}
+
+impl GradientConfigBuilder {
+	pub fn resize_strategy(mut self, resize_strategy: ResizeStrategy) -> Self {
+		self.config.resize_strategy = resize_strategy;
+		self
+	}
+
+	pub fn blur_sigma(mut self, blur_sigma: f64) -> Self {
+		self.config.blur_sigma = blur_sigma;
+		self
+	}
+
+	pub fn magnitude_threshold(mut self, magnitude_threshold: ThresholdMode) -> Self {
+		self.config.magnitude_threshold = magnitude_threshold;
+		self
+	}
+
+	pub fn band_frac(mut self, band_frac: f64) -> Self {
+		self.config.band_frac = band_frac;
+		self
+	}
+
+	pub fn average_space(mut self, average_space: ColorAverageSpace) -> Self {
+		self.config.average_space = average_space;
+		self
+	}
+
+	pub fn color_statistic(mut self, color_statistic: ColorStatistic) -> Self {
+		self.config.color_statistic = color_statistic;
+		self
+	}
+
+	pub fn stop_selection(mut self, stop_selection: StopSelection) -> Self {
+		self.config.stop_selection = stop_selection;
+		self
+	}
+
+	pub fn linearize(mut self, linearize: bool) -> Self {
+		self.config.linearize = linearize;
+		self
+	}
+
+	pub fn rounding(mut self, rounding: RoundingMode) -> Self {
+		self.config.rounding = rounding;
+		self
+	}
+
+	pub fn edge_operator(mut self, edge_operator: EdgeOperator) -> Self {
+		self.config.edge_operator = edge_operator;
+		self
+	}
+
+	pub fn min_coherence(mut self, min_coherence: f64) -> Self {
+		self.config.min_coherence = min_coherence;
+		self
+	}
+
+	pub fn radial_margin(mut self, radial_margin: f64) -> Self {
+		self.config.radial_margin = radial_margin;
+		self
+	}
+
+	pub fn snap_angle(mut self, tolerance: Option<f64>) -> Self {
+		self.config.snap_angle = tolerance;
+		self
+	}
+
+	pub fn snap_step(mut self, snap_step: f64) -> Self {
+		self.config.snap_step = snap_step;
+		self
+	}
+
+	pub fn compute_fit_error(mut self, compute_fit_error: bool) -> Self {
+		self.config.compute_fit_error = compute_fit_error;
+		self
+	}
+
+	pub fn border_exclude_frac(mut self, border_exclude_frac: f64) -> Self {
+		self.config.border_exclude_frac = border_exclude_frac;
+		self
+	}
+
+	pub fn respect_exif(mut self, respect_exif: bool) -> Self {
+		self.config.respect_exif = respect_exif;
+		self
+	}
+
+	pub fn use_opencl(mut self, use_opencl: bool) -> Self {
+		self.config.use_opencl = use_opencl;
+		self
+	}
+
+	pub fn high_bit_depth(mut self, high_bit_depth: bool) -> Self {
+		self.config.high_bit_depth = high_bit_depth;
+		self
+	}
+
+	pub fn white_balance(mut self, white_balance: bool) -> Self {
+		self.config.white_balance = white_balance;
+		self
+	}
+
+	pub fn normalize_contrast(mut self, normalize_contrast: bool) -> Self {
+		self.config.normalize_contrast = normalize_contrast;
+		self
+	}
+
+	pub fn denoise_dither(mut self, denoise_dither: bool) -> Self {
+		self.config.denoise_dither = denoise_dither;
+		self
+	}
+
+	pub fn orientation(mut self, orientation: Orientation) -> Self {
+		self.config.orientation = orientation;
+		self
+	}
+
+	pub fn per_channel_angles(mut self, per_channel_angles: bool) -> Self {
+		self.config.per_channel_angles = per_channel_angles;
+		self
+	}
+
+	#[cfg(feature = "opencv")]
+	pub fn interpolation(mut self, interpolation: i32) -> Self {
+		self.config.interpolation = interpolation;
+		self
+	}
+
+	#[cfg(feature = "memmap")]
+	pub fn memory_map(mut self, memory_map: bool) -> Self {
+		self.config.memory_map = memory_map;
+		self
+	}
+
+	#[cfg(feature = "opencv")]
+	pub fn roi(mut self, roi: Option<opencv::core::Rect>) -> Self {
+		self.config.roi = roi;
+		self
+	}
+
+	pub fn build(self) -> Result<GradientConfig> {
+		self.config.validate()?;
+		Ok(self.config)
+	}
+}
+
+/// Derivative filter used to estimate the gradient direction. Scharr is
+/// more accurate than a 3x3 Sobel on small inputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeOperator {
+	Sobel { ksize: i32 },
+	Scharr,
+}
+
+impl EdgeOperator {
+	pub(crate) fn validate(&self) -> Result<()> {
+		match *self {
+			Self::Sobel { ksize } if ![1, 3, 5, 7].contains(&ksize) => {
+				Err(GradientError::InvalidArgument(format!(
+					"Sobel ksize must be 1, 3, 5 or 7, got {}",
+					ksize
+				)))
+			}
+			_ => Ok(()),
+		}
+	}
+
+	pub(crate) fn kernel_size(&self) -> i32 {
+		match *self {
+			Self::Sobel { ksize } => ksize,
+			Self::Scharr => 3,
+		}
+	}
+}
+
+impl Default for EdgeOperator {
+	fn default() -> Self {
+		Self::Sobel { ksize: 5 }
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Asserts that `config` is rejected with a message naming `field`.
+	fn rejects(config: GradientConfig, field: &str) {
+		match config.validate() {
+			Err(GradientError::InvalidArgument(message)) => {
+				assert!(message.contains(field), "{}: {}", field, message)
+			}
+			other => panic!("{}: expected InvalidArgument, got {:?}", field, other),
+		}
+	}
+
+	#[test]
+	fn default_is_valid() {
+		GradientConfig::default().validate().unwrap();
+	}
+
+	#[test]
+	fn out_of_range_fields_are_rejected() {
+		let default = GradientConfig::default;
+		rejects(
+			GradientConfig {
+				resize_strategy: ResizeStrategy::LongestSide(4),
+				..default()
+			},
+			"resize_strategy",
+		);
+		rejects(
+			GradientConfig {
+				blur_sigma: -1.0,
+				..default()
+			},
+			"blur_sigma",
+		);
+		rejects(
+			GradientConfig {
+				blur_sigma: f64::INFINITY,
+				..default()
+			},
+			"blur_sigma",
+		);
+		rejects(
+			GradientConfig {
+				band_frac: 0.0,
+				..default()
+			},
+			"band_frac",
+		);
+		rejects(
+			GradientConfig {
+				band_frac: 0.6,
+				..default()
+			},
+			"band_frac",
+		);
+		rejects(
+			GradientConfig {
+				border_exclude_frac: 0.5,
+				..default()
+			},
+			"border_exclude_frac",
+		);
+		rejects(
+			GradientConfig {
+				min_coherence: 1.5,
+				..default()
+			},
+			"min_coherence",
+		);
+		rejects(
+			GradientConfig {
+				radial_margin: f64::NAN,
+				..default()
+			},
+			"radial_margin",
+		);
+		rejects(
+			GradientConfig {
+				snap_angle: Some(-1.0),
+				..default()
+			},
+			"snap_angle",
+		);
+		rejects(
+			GradientConfig {
+				snap_step: 0.0,
+				..default()
+			},
+			"snap_step",
+		);
+		rejects(
+			GradientConfig {
+				snap_step: f64::NAN,
+				..default()
+			},
+			"snap_step",
+		);
+		rejects(
+			GradientConfig {
+				magnitude_threshold: ThresholdMode::FracOfMax(1.5),
+				..default()
+			},
+			"FracOfMax",
+		);
+		rejects(
+			GradientConfig {
+				magnitude_threshold: ThresholdMode::Percentile(101.0),
+				..default()
+			},
+			"Percentile",
+		);
+		rejects(
+			GradientConfig {
+				edge_operator: EdgeOperator::Sobel { ksize: 4 },
+				..default()
+			},
+			"ksize",
+		);
+		rejects(
+			GradientConfig {
+				color_statistic: ColorStatistic::TrimmedMean { trim_frac: 0.5 },
+				..default()
+			},
+			"trim_frac",
+		);
+		rejects(
+			GradientConfig {
+				stop_selection: StopSelection::KMeans { k: 0 },
+				..default()
+			},
+			"KMeans",
+		);
+		#[cfg(feature = "opencv")]
+		rejects(
+			GradientConfig {
+				interpolation: opencv::imgproc::INTER_MAX,
+				..default()
+			},
+			"interpolation",
+		);
+	}
+
+	#[test]
+	fn narrow_columns_resize_by_their_longest_side() {
+		let fixed = ResizeStrategy::FixedWidth(100);
+		assert_eq!(fixed.target_size(200.0, 600.0), (100, 300));
+		assert_eq!(fixed.target_size(1.0, 10000.0), (1, 100));
+		assert_eq!(fixed.target_size(10000.0, 1.0), (100, 1));
+		assert_eq!(
+			ResizeStrategy::LongestSide(100).target_size(200.0, 600.0),
+			(33, 100)
+		);
+	}
+
+	#[test]
+	fn builder_rejects_what_validate_rejects() {
+		assert!(matches!(
+			GradientConfig::builder().snap_step(-45.0).build(),
+			Err(GradientError::InvalidArgument(_))
+		));
+	}
+
+	#[cfg(any(feature = "opencv", feature = "pure-rust"))]
+	#[test]
+	fn invalid_config_fails_before_the_file_is_read() {
+		let config = GradientConfig {
+			band_frac: 0.0,
+			..GradientConfig::default()
+		};
+		let result =
+			crate::extract_gradient_hex_with_config(std::path::Path::new("missing.png"), &config);
+		assert!(matches!(result, Err(GradientError::InvalidArgument(_))));
+	}
+}
@@ -0,0 +1,104 @@
+use opencv::{
+	core::{self, CV_8U, Mat},
+	imgcodecs, imgproc,
+	prelude::*,
+};
+use std::path::Path;
+
+use crate::pipeline::{
+	Analysis, Scratch, analyze, fit_residual, gradient_from_analysis, read_image,
+};
+use crate::{GradientConfig, GradientError, GradientResult, Result};
+
+/// Residuals of this many 8-bit levels and more get the hottest color of
+/// the heatmap; smaller ones are scaled linearly.
+const RESIDUAL_FULL_SCALE: f64 = 64.0;
+
+/// Intermediate images of one extraction, all at the resized resolution and
+/// 8-bit single channel so they can be written out with `imwrite`.
+pub struct DebugMaps {
+	/// 255 where a pixel's gradient was used for the angle, 0 elsewhere.
+	pub valid_mask: Mat,
+	/// Gradient magnitude scaled so the strongest pixel is 255.
+	pub magnitude: Mat,
+	/// Position along the gradient direction, scaled from its minimum over
+	/// the whole image at 0 to its maximum at 255.
+	pub projection: Mat,
+}
+
+pub fn extract_gradient_debug(image_path: &Path) -> Result<(GradientResult, DebugMaps)> {
+	extract_gradient_debug_with_config(image_path, &GradientConfig::default())
+}
+
+/// Like `extract_gradient_hex_with_config`, also returning the maps the
+/// result was derived from, for looking into misdetections.
+pub fn extract_gradient_debug_with_config(
+	image_path: &Path,
+	config: &GradientConfig,
+) -> Result<(GradientResult, DebugMaps)> {
+	let img = read_image(image_path, config)?;
+	let analysis = analyze(&img, config, &mut Scratch::default())?;
+	let result = gradient_from_analysis(&analysis, config)?;
+	Ok((result, debug_maps(&analysis)?))
+}
+
+pub fn write_fit_residual(image_path: &Path, out: &Path) -> Result<()> {
+	write_fit_residual_with_config(image_path, out, &GradientConfig::default())
+}
+
+/// Writes a heatmap, at the resized resolution, of how far each pixel of the
+/// blurred image is from the reconstructed two-stop gradient, the per-pixel
+/// version of `GradientResult::fit_error`. Text and objects on top of the
+/// gradient light up. The format follows the extension of `out`.
+pub fn write_fit_residual_with_config(
+	image_path: &Path,
+	out: &Path,
+	config: &GradientConfig,
+) -> Result<()> {
+	let out_str = out
+		.to_str()
+		.ok_or_else(|| GradientError::InvalidPath(out.to_path_buf()))?;
+
+	let img = read_image(image_path, config)?;
+	let analysis = analyze(&img, config, &mut Scratch::default())?;
+	let result = gradient_from_analysis(&analysis, config)?;
+	let [start, end] = [result.start_rgb, result.end_rgb]
+		.map(|[r, g, b]| core::Vec3d::from([b as f64, g as f64, r as f64]));
+	let residual = fit_residual(&analysis, start, end)?;
+
+	let mut scaled = Mat::default();
+	residual.convert_to(&mut scaled, CV_8U, 255.0 / RESIDUAL_FULL_SCALE, 0.0)?;
+	let mut heatmap = Mat::default();
+	imgproc::apply_color_map(&scaled, &mut heatmap, imgproc::COLORMAP_JET)?;
+
+	if !imgcodecs::imwrite(out_str, &heatmap, &core::Vector::new())? {
+		return Err(GradientError::UnsupportedFormat(format!(
+			"Could not write {}",
+			out.display()
+		)));
+	}
+	Ok(())
+}
+
+fn debug_maps(analysis: &Analysis) -> Result<DebugMaps> {
+	Ok(DebugMaps {
+		valid_mask: analysis.field.valid.clone(),
+		magnitude: to_8bit(&analysis.field.mag)?,
+		projection: to_8bit(&analysis.t)?,
+	})
+}
+
+/// Stretches a single-channel map to `[0, 255]`.
+fn to_8bit(map: &Mat) -> Result<Mat> {
+	let mut scaled = Mat::default();
+	core::normalize(
+		map,
+		&mut scaled,
+		0.0,
+		255.0,
+		core::NORM_MINMAX,
+		CV_8U,
+		&core::no_array(),
+	)?;
+	Ok(scaled)
+}
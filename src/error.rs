@@ -0,0 +1,37 @@
+use std::path::PathBuf;
+
+#[derive(Debug, thiserror::Error)]
+pub enum GradientError {
+	#[error("Not a valid filepath: {0:?}")]
+	InvalidPath(PathBuf),
+	#[error("Failed to read image at {path:?}")]
+	ReadFailed {
+		path: PathBuf,
+		#[source]
+		source: std::io::Error,
+	},
+	#[error("Failed to write {path:?}")]
+	WriteFailed {
+		path: PathBuf,
+		#[source]
+		source: std::io::Error,
+	},
+	#[error("Image is empty")]
+	EmptyImage,
+	#[error("Unsupported image format: {0}")]
+	UnsupportedFormat(String),
+	#[error("Invalid argument: {0}")]
+	InvalidArgument(String),
+	#[cfg(feature = "opencv")]
+	#[error(transparent)]
+	OpenCv(#[from] opencv::Error),
+	#[cfg(any(feature = "image", feature = "pure-rust"))]
+	#[error(transparent)]
+	Image(#[from] image::ImageError),
+	/// The blocking task was cancelled because the runtime shut down.
+	#[cfg(feature = "tokio")]
+	#[error(transparent)]
+	Join(#[from] tokio::task::JoinError),
+}
+
+pub type Result<T, E = GradientError> = std::result::Result<T, E>;
@@ -0,0 +1,44 @@
+use std::path::Path;
+
+use crate::pipeline::{Scratch, decode_image, extract_gradient, read_image_with};
+use crate::{GradientConfig, GradientResult, Result};
+
+/// Extracts gradients with a fixed config, keeping the file buffer and the
+/// intermediate Mats between calls. Worth it when calling in a loop over
+/// images of the same size; results match `extract_gradient_hex_with_config`.
+pub struct GradientExtractor {
+	config: GradientConfig,
+	scratch: Scratch,
+}
+
+impl GradientExtractor {
+	pub fn new(config: GradientConfig) -> Self {
+		Self {
+			config,
+			scratch: Scratch::default(),
+		}
+	}
+
+	pub fn config(&self) -> &GradientConfig {
+		&self.config
+	}
+
+	/// Reads the file into the kept buffer, or maps it with
+	/// `GradientConfig::memory_map`.
+	pub fn extract(&mut self, image_path: &Path) -> Result<GradientResult> {
+		let img = read_image_with(image_path, &self.config, &mut self.scratch.data)?;
+		extract_gradient(&img, &self.config, &mut self.scratch)
+	}
+
+	pub fn extract_from_bytes(&mut self, data: &[u8]) -> Result<GradientResult> {
+		self.config.validate()?;
+		let img = decode_image(data, &self.config)?;
+		extract_gradient(&img, &self.config, &mut self.scratch)
+	}
+}
+
+impl Default for GradientExtractor {
+	fn default() -> Self {
+		Self::new(GradientConfig::default())
+	}
+}
@@ -0,0 +1,273 @@
+use std::f64::consts::FRAC_PI_2;
+
+use opencv::{
+	core::{self, CV_32F, Mat, Scalar},
+	prelude::*,
+};
+
+use crate::angle::{DominantDirection, GradientField, MIN_VALID_PIXELS};
+use crate::pipeline::{Analysis, band_mask, bgr_to_rgb};
+use crate::{GradientConfig, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum GradientKind {
+	Linear,
+	Radial,
+	/// Experimental: colors sweep around a center, as in CSS
+	/// `conic-gradient()`.
+	Conic,
+	Flat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RadialInfo {
+	pub center: (f64, f64),
+	pub inner_color: [u8; 3],
+	pub outer_color: [u8; 3],
+}
+
+/// The sweep of a conic gradient, clockwise from `start_angle` (compass
+/// degrees around `center`) back round to it, where the colors jump from
+/// `end_color` to `start_color`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConicInfo {
+	pub center: (f64, f64),
+	pub start_angle: f64,
+	pub start_color: [u8; 3],
+	pub end_color: [u8; 3],
+}
+
+/// Angular buckets the colors around a conic center are averaged over to
+/// find the seam.
+const CONIC_BINS: usize = 36;
+
+/// Below this ratio of `det / trace^2` of the least-squares system the
+/// gradient lines are considered parallel and no center is reported.
+const MIN_CENTER_CONDITIONING: f64 = 1e-3;
+
+/// A gradient is linear when its directions are parallel (`confidence`),
+/// radial when they point away from a common center, conic when they run
+/// around one, and flat when no coherence reaches `config.min_coherence`.
+/// Radial and conic have to beat linear by `config.radial_margin`, because
+/// the extra freedom of the center fit lets them match near-linear fields
+/// about as well.
+pub(crate) fn classify(
+	field: &GradientField,
+	direction: &DominantDirection,
+	config: &GradientConfig,
+) -> Result<(GradientKind, Option<(f64, f64)>)> {
+	if field.valid_count < MIN_VALID_PIXELS {
+		return Ok((GradientKind::Flat, None));
+	}
+
+	let radial = centered_coherence(field, 0.0)?;
+	let conic = centered_coherence(field, FRAC_PI_2)?;
+	let (centered_kind, (center, coherence)) = if conic.1 > radial.1 {
+		(GradientKind::Conic, conic)
+	} else {
+		(GradientKind::Radial, radial)
+	};
+
+	let kind = if coherence > direction.confidence + config.radial_margin
+		&& coherence >= config.min_coherence
+	{
+		centered_kind
+	} else if direction.confidence >= config.min_coherence {
+		GradientKind::Linear
+	} else {
+		GradientKind::Flat
+	};
+
+	let center = if kind == centered_kind { center } else { None };
+
+	Ok((kind, center))
+}
+
+/// Center of the lines along the gradient directions turned by `offset`,
+/// and how well the directions agree with it.
+fn centered_coherence(field: &GradientField, offset: f64) -> Result<(Option<(f64, f64)>, f64)> {
+	let center = line_center(field, offset)?;
+	let coherence = match center {
+		Some(center) => coherence_around(field, center, offset)?,
+		None => 0.0,
+	};
+	Ok((center, coherence))
+}
+
+/// Inner and outer colors are sampled from the `band_frac` closest and
+/// farthest parts of the image, measured by distance from `center`.
+pub(crate) fn radial_info(
+	analysis: &Analysis,
+	center: (f64, f64),
+	config: &GradientConfig,
+) -> Result<RadialInfo> {
+	let blurred = &analysis.blurred;
+	let mut radius =
+		Mat::new_rows_cols_with_default(blurred.rows(), blurred.cols(), CV_32F, Scalar::all(0.0))?;
+	for y in 0..radius.rows() {
+		let row = radius.at_row_mut::<f32>(y)?;
+		for (x, value) in row.iter_mut().enumerate() {
+			*value = (x as f64 - center.0).hypot(y as f64 - center.1) as f32;
+		}
+	}
+
+	let mut r_min = 0.0;
+	let mut r_max = 0.0;
+	core::min_max_loc(
+		&radius,
+		Some(&mut r_min),
+		Some(&mut r_max),
+		None,
+		None,
+		&core::no_array(),
+	)?;
+
+	let inner_high = r_min + config.band_frac * (r_max - r_min);
+	let outer_low = r_max - config.band_frac * (r_max - r_min);
+
+	let inner_mask = analysis.restrict(band_mask(&radius, f64::NEG_INFINITY, inner_high)?)?;
+	let outer_mask = analysis.restrict(band_mask(&radius, outer_low, f64::INFINITY)?)?;
+
+	let black = core::Vec3d::all(0.0);
+	Ok(RadialInfo {
+		center,
+		inner_color: bgr_to_rgb(analysis.band_color(&inner_mask)?.unwrap_or(black)),
+		outer_color: bgr_to_rgb(analysis.band_color(&outer_mask)?.unwrap_or(black)),
+	})
+}
+
+/// Start angle and end colors of a conic gradient around `center`. The
+/// seam is the largest step between neighbouring angular buckets; the end
+/// colors are sampled from the `band_frac` of the turn on either side of it.
+pub(crate) fn conic_info(
+	analysis: &Analysis,
+	center: (f64, f64),
+	config: &GradientConfig,
+) -> Result<ConicInfo> {
+	let mut blurred = Mat::default();
+	analysis
+		.blurred
+		.convert_to(&mut blurred, CV_32F, 1.0, 0.0)?;
+	let mut around =
+		Mat::new_rows_cols_with_default(blurred.rows(), blurred.cols(), CV_32F, Scalar::all(0.0))?;
+	let mut sums = [(0.0, 0usize); CONIC_BINS];
+	for y in 0..blurred.rows() {
+		let pixels = blurred.at_row::<core::Vec3f>(y)?;
+		let opaque_row = match &analysis.opaque {
+			Some(opaque) => Some(opaque.at_row::<u8>(y)?),
+			None => None,
+		};
+		let row = around.at_row_mut::<f32>(y)?;
+		for (x, (value, pixel)) in row.iter_mut().zip(pixels).enumerate() {
+			let compass = (x as f64 - center.0)
+				.atan2(center.1 - y as f64)
+				.to_degrees()
+				.rem_euclid(360.0);
+			*value = compass as f32;
+			if opaque_row.is_some_and(|row| row[x] == 0) {
+				continue;
+			}
+			let bin = ((compass / 360.0 * CONIC_BINS as f64) as usize).min(CONIC_BINS - 1);
+			sums[bin].0 += (pixel[0] + pixel[1] + pixel[2]) as f64;
+			sums[bin].1 += 1;
+		}
+	}
+
+	let profile = sums.map(|(sum, count)| (count > 0).then(|| sum / count as f64));
+	let seam = (0..CONIC_BINS)
+		.filter_map(|i| {
+			let next = (i + 1) % CONIC_BINS;
+			Some((next, (profile[next]? - profile[i]?).abs()))
+		})
+		.max_by(|a, b| a.1.total_cmp(&b.1))
+		.map_or(0, |(next, _)| next);
+	let start_angle = seam as f64 * 360.0 / CONIC_BINS as f64;
+
+	// Measured from the seam, so both wedges are plain ranges.
+	for y in 0..around.rows() {
+		for value in around.at_row_mut::<f32>(y)? {
+			*value = (*value - start_angle as f32).rem_euclid(360.0);
+		}
+	}
+	let width = config.band_frac * 360.0;
+	let start_mask = analysis.restrict(band_mask(&around, 0.0, width)?)?;
+	let end_mask = analysis.restrict(band_mask(&around, 360.0 - width, 360.0)?)?;
+
+	let black = core::Vec3d::all(0.0);
+	Ok(ConicInfo {
+		center,
+		start_angle,
+		start_color: bgr_to_rgb(analysis.band_color(&start_mask)?.unwrap_or(black)),
+		end_color: bgr_to_rgb(analysis.band_color(&end_mask)?.unwrap_or(black)),
+	})
+}
+
+/// Least-squares intersection of the lines running through every valid
+/// pixel along its gradient direction turned by `offset` radians.
+fn line_center(field: &GradientField, offset: f64) -> Result<Option<(f64, f64)>> {
+	let (mut a11, mut a12, mut a22) = (0.0, 0.0, 0.0);
+	let (mut b1, mut b2) = (0.0, 0.0);
+
+	for y in 0..field.angle.rows() {
+		let mask_row = field.valid.at_row::<u8>(y)?;
+		let angle_row = field.angle.at_row::<f64>(y)?;
+		let mag_row = field.mag.at_row::<f64>(y)?;
+		for (x, ((&m, &a), &weight)) in mask_row.iter().zip(angle_row).zip(mag_row).enumerate() {
+			if m == 0 {
+				continue;
+			}
+
+			let (nx, ny) = ((a + offset).cos(), (a + offset).sin());
+			let (p11, p12, p22) = (1.0 - nx * nx, -nx * ny, 1.0 - ny * ny);
+			let (px, py) = (x as f64, y as f64);
+			a11 += weight * p11;
+			a12 += weight * p12;
+			a22 += weight * p22;
+			b1 += weight * (p11 * px + p12 * py);
+			b2 += weight * (p12 * px + p22 * py);
+		}
+	}
+
+	let det = a11 * a22 - a12 * a12;
+	let trace = a11 + a22;
+	if trace == 0.0 || det / (trace * trace) < MIN_CENTER_CONDITIONING {
+		return Ok(None);
+	}
+
+	Ok(Some((
+		(a22 * b1 - a12 * b2) / det,
+		(a11 * b2 - a12 * b1) / det,
+	)))
+}
+
+/// Same measure as `confidence`, but taken relative to the direction from
+/// `center` to each pixel, turned by `offset`, instead of a single global
+/// direction.
+fn coherence_around(field: &GradientField, center: (f64, f64), offset: f64) -> Result<f64> {
+	let mut sum = 0.0;
+	let mut sum_weight = 0.0;
+
+	for y in 0..field.angle.rows() {
+		let mask_row = field.valid.at_row::<u8>(y)?;
+		let angle_row = field.angle.at_row::<f64>(y)?;
+		let mag_row = field.mag.at_row::<f64>(y)?;
+		for (x, ((&m, &a), &weight)) in mask_row.iter().zip(angle_row).zip(mag_row).enumerate() {
+			let (rx, ry) = (x as f64 - center.0, y as f64 - center.1);
+			if m == 0 || (rx == 0.0 && ry == 0.0) {
+				continue;
+			}
+
+			sum += weight * (2.0 * (a - offset - ry.atan2(rx))).cos();
+			sum_weight += weight;
+		}
+	}
+
+	if sum_weight == 0.0 {
+		return Ok(0.0);
+	}
+
+	Ok((sum / sum_weight).clamp(0.0, 1.0))
+}
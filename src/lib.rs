@@ -1,231 +1,84 @@
-use anyhow::{Context, Result};
-use opencv::core::CV_32F;
-use opencv::{
-	core::{self, BorderTypes, CV_8UC1, CV_64F, Mat, Scalar},
-	imgcodecs, imgproc,
-	prelude::*,
-	viz::Color,
+#[cfg(feature = "opencv")]
+mod accent;
+#[cfg(feature = "opencv")]
+mod angle;
+#[cfg(feature = "opencv")]
+mod batch;
+mod config;
+#[cfg(feature = "opencv")]
+mod debug;
+mod error;
+#[cfg(feature = "opencv")]
+mod extractor;
+#[cfg(feature = "opencv")]
+mod kind;
+#[cfg(feature = "tokio")]
+mod nonblocking;
+#[cfg(feature = "opencv")]
+mod output;
+#[cfg(feature = "opencv")]
+mod pipeline;
+#[cfg(feature = "pure-rust")]
+pub mod pure;
+#[cfg(feature = "opencv")]
+mod quality;
+#[cfg(feature = "opencv")]
+mod report;
+#[cfg(feature = "opencv")]
+mod timing;
+
+#[cfg(feature = "opencv")]
+pub use self::{
+	accent::{extract_gradient_and_accent, extract_gradient_and_accent_with_config},
+	angle::estimate_gradient_angle,
+	batch::{
+		extract_gradient_hex_batch, extract_gradient_hex_batch_with,
+		extract_gradient_hex_batch_with_config,
+	},
+	debug::{
+		DebugMaps, extract_gradient_debug, extract_gradient_debug_with_config, write_fit_residual,
+		write_fit_residual_with_config,
+	},
+	extractor::GradientExtractor,
+	kind::{ConicInfo, GradientKind, RadialInfo},
+	output::{AngleConvention, HexStyle, write_ggr_stops},
+	pipeline::{
+		GradientResult, PALETTE_MAX_COLORS, detect_stop_count, detect_stop_count_with_config,
+		extract_angle_histogram, extract_angle_histogram_with_config, extract_gradient_frames,
+		extract_gradient_frames_with_config, extract_gradient_from_mat,
+		extract_gradient_from_mat_with_config, extract_gradient_hex,
+		extract_gradient_hex_from_bytes, extract_gradient_hex_from_bytes_with_config,
+		extract_gradient_hex_with_config, extract_gradient_stops,
+		extract_gradient_stops_with_config, extract_gradient_with_mask, sample_band_color,
+		sample_band_color_with_config, try_extract_gradient, try_extract_gradient_with_config,
+	},
+	quality::{extract_with_quality, extract_with_quality_with_config},
+	report::{GradientReport, REPORT_VERSION, extract_gradient_report},
+	timing::{Timing, extract_with_timing, extract_with_timing_with_config},
 };
-use std::path::Path;
-
-#[derive(Debug)]
-pub struct GradientResult {
-	pub start_color: Color,
-	pub end_color: Color,
-	pub angle: f64,
-}
-
-pub fn extract_gradient_hex(
-	image_path: &Path,
-	resize_width: i32,
-	blur_sigma: f64,
-) -> Result<GradientResult> {
-	let img = imgcodecs::imread(
-		image_path.to_str().context("Not a valid filepath")?,
-		imgcodecs::IMREAD_COLOR,
-	)
-	.context("Failed to read image")?;
-
-	if img.empty() {
-		anyhow::bail!("Image is empty at {:?}", image_path);
-	}
-
-	let size = img.size()?;
-	let mut small = Mat::default();
-	imgproc::resize(
-		&img,
-		&mut small,
-		core::Size::new(resize_width, resize_width * size.height / size.width),
-		0.0,
-		0.0,
-		imgproc::INTER_AREA,
-	)?;
-
-	let mut blurred = Mat::default();
-	imgproc::gaussian_blur(
-		&small,
-		&mut blurred,
-		core::Size::new(0, 0),
-		blur_sigma,
-		blur_sigma,
-		BorderTypes::BORDER_REFLECT_101 as i32,
-		core::AlgorithmHint::ALGO_HINT_ACCURATE,
-	)?;
-
-	let mut gray = Mat::default();
-	imgproc::cvt_color(
-		&blurred,
-		&mut gray,
-		imgproc::COLOR_BGR2GRAY,
-		0,
-		core::AlgorithmHint::ALGO_HINT_DEFAULT,
-	)?;
-
-	let mut grad_x = Mat::default();
-	imgproc::sobel(
-		&gray,
-		&mut grad_x,
-		CV_64F,
-		1,
-		0,
-		5,
-		1.0,
-		0.0,
-		BorderTypes::BORDER_REFLECT_101 as i32,
-	)?;
-
-	let mut grad_y = Mat::default();
-	imgproc::sobel(
-		&gray,
-		&mut grad_y,
-		CV_64F,
-		0,
-		1,
-		5,
-		1.0,
-		0.0,
-		BorderTypes::BORDER_REFLECT_101 as i32,
-	)?;
-
-	let mut mag = Mat::default();
-	let mut angle_rad = Mat::default();
-	core::cart_to_polar(&grad_x, &grad_y, &mut mag, &mut angle_rad, false)?;
-
-	let mut max_val = 0.0;
-	core::min_max_loc(
-		&mag,
-		None,
-		Some(&mut max_val),
-		None,
-		None,
-		&core::no_array(),
-	)?;
-	let threshold = 0.1 * max_val;
-
-	let mut valid_mask = Mat::default();
-	imgproc::threshold(
-		&mag,
-		&mut valid_mask,
-		threshold,
-		255.0,
-		imgproc::THRESH_BINARY,
-	)?;
-	let mut valid_mask_output = valid_mask.clone();
-	valid_mask.convert_to(&mut valid_mask_output, CV_8UC1, 1.0, 0.0)?;
-
-	let non_zero_count = core::count_non_zero(&valid_mask_output)?;
-	let dominant_angle = if non_zero_count < 10 {
-		0.0
-	} else {
-		let mask_data = valid_mask_output.data_typed::<u8>()?;
-		let angle_data = angle_rad.data_typed::<f64>()?;
-		let cols = angle_rad.cols() as usize;
-		let rows = angle_rad.rows() as usize;
-
-		let mut sum_cos = 0.0;
-		let mut sum_sin = 0.0;
-		let mut count = 0;
-
-		for y in 0..rows {
-			for x in 0..cols {
-				let idx = y * cols + x;
-				if mask_data[idx] != 0 {
-					let a = angle_data[idx];
-					let double_angle = 2.0 * a;
-					sum_cos += double_angle.cos();
-					sum_sin += double_angle.sin();
-					count += 1;
-				}
-			}
-		}
-
-		if count == 0 {
-			0.0
-		} else {
-			let avg_cos = sum_cos / count as f64;
-			let avg_sin = sum_sin / count as f64;
-			0.5 * avg_sin.atan2(avg_cos)
-		}
-	};
-
+#[cfg(all(feature = "opencv", feature = "rayon"))]
+pub use batch::extract_gradient_hex_batch_par;
+pub use config::{
+	ColorAverageSpace, ColorStatistic, EdgeOperator, GradientConfig, GradientConfigBuilder,
+	Orientation, ResizeStrategy, RoundingMode, StopSelection, ThresholdMode,
+};
+pub use error::{GradientError, Result};
+#[cfg(feature = "tokio")]
+pub use nonblocking::{extract_gradient_hex_async, extract_gradient_hex_async_with_config};
+#[cfg(all(feature = "opencv", feature = "image"))]
+pub use pipeline::{extract_gradient_from_rgb, extract_gradient_from_rgb_with_config};
+#[cfg(all(feature = "pure-rust", not(feature = "opencv")))]
+pub use pure::{GradientResult, extract_gradient_hex, extract_gradient_hex_with_config};
+
+/// Images with fewer pixels than this are processed on one thread; below it
+/// rayon's scheduling costs more than the per-pixel loops.
+#[cfg(all(feature = "opencv", feature = "rayon"))]
+const PARALLEL_MIN_PIXELS: usize = 64 * 1024;
+
+#[cfg(any(feature = "opencv", feature = "pure-rust"))]
+fn compass_angle(dominant_angle: f64) -> f64 {
 	let dx = dominant_angle.cos();
 	let dy = dominant_angle.sin();
 	let cartesian_angle_rad = f64::atan2(-dy, dx);
-	let angle = (90.0 - cartesian_angle_rad.to_degrees()).rem_euclid(360.0);
-
-	let h = blurred.rows();
-	let w = blurred.cols();
-
-	let mut t = Mat::new_rows_cols_with_default(h, w, CV_32F, Scalar::all(0.0))?;
-	{
-		let cols = t.cols() as usize;
-		let t_data = t.data_typed_mut::<f32>()?;
-
-		for y in 0..h {
-			for x in 0..w {
-				let idx = (y as usize) * cols + (x as usize);
-				t_data[idx] = (x as f32) * dx as f32 + (y as f32) * dy as f32;
-			}
-		}
-	}
-
-	let mut min_val = 0.0;
-	let mut max_val = 0.0;
-	core::min_max_loc(
-		&t,
-		Some(&mut min_val),
-		Some(&mut max_val),
-		None,
-		None,
-		&core::no_array(),
-	)?;
-
-	let threshold_low = min_val + 0.15 * (max_val - min_val);
-	let threshold_high = max_val - 0.15 * (max_val - min_val);
-
-	let mut start_mask = Mat::default();
-	core::in_range(
-		&t,
-		&Scalar::all(f64::NEG_INFINITY),
-		&Scalar::all(threshold_low as f64),
-		&mut start_mask,
-	)?;
-
-	let mut end_mask = Mat::default();
-	core::in_range(
-		&t,
-		&Scalar::all(threshold_high as f64),
-		&Scalar::all(f64::INFINITY),
-		&mut end_mask,
-	)?;
-
-	let get_avg_color = |mask: &Mat| -> Result<core::Vec3d> {
-		let mean_val = core::mean(&blurred, mask)?;
-		let b = mean_val[0].clamp(0.0, 255.0).round();
-		let g = mean_val[1].clamp(0.0, 255.0).round();
-		let r = mean_val[2].clamp(0.0, 255.0).round();
-		Ok(core::Vec3d::from([b, g, r]))
-	};
-
-	let start_bgr = if core::count_non_zero(&start_mask)? > 0 {
-		get_avg_color(&start_mask)?
-	} else {
-		core::Vec3d::all(0.0)
-	};
-
-	let end_bgr = if core::count_non_zero(&end_mask)? > 0 {
-		get_avg_color(&end_mask)?
-	} else {
-		core::Vec3d::all(0.0)
-	};
-
-	let start_color = Color::new_1(start_bgr[0], start_bgr[1], start_bgr[2])?;
-
-	let end_color = Color::new_1(end_bgr[0], end_bgr[1], end_bgr[2])?;
-
-	Ok(GradientResult {
-		start_color: start_color,
-		end_color: end_color,
-		angle: angle,
-	})
+	(90.0 - cartesian_angle_rad.to_degrees()).rem_euclid(360.0)
 }
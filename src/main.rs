@@ -0,0 +1,136 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use gradient_generator::{
+	GradientConfig, GradientError, GradientResult, ResizeStrategy,
+	extract_gradient_hex_from_bytes_with_config, extract_gradient_hex_with_config,
+};
+
+const USAGE: &str =
+	"usage: gradient-generator [--resize N] [--sigma S] [--format tsv|json] IMAGE...
+An IMAGE of - reads the image from stdin.";
+
+enum Format {
+	Tsv,
+	Json,
+}
+
+struct Args {
+	paths: Vec<PathBuf>,
+	config: GradientConfig,
+	format: Format,
+}
+
+fn parse_args() -> Result<Args, String> {
+	let mut paths = Vec::new();
+	let mut config = GradientConfig::default();
+	let mut format = Format::Tsv;
+
+	let mut args = std::env::args().skip(1);
+	while let Some(arg) = args.next() {
+		let mut value = |name: &str| {
+			args.next()
+				.ok_or_else(|| format!("{} requires a value", name))
+		};
+		match arg.as_str() {
+			"--resize" => {
+				let value = value("--resize")?;
+				config.resize_strategy = ResizeStrategy::FixedWidth(
+					value
+						.parse()
+						.map_err(|_| format!("invalid --resize value {:?}", value))?,
+				);
+			}
+			"--sigma" => {
+				let value = value("--sigma")?;
+				config.blur_sigma = value
+					.parse()
+					.map_err(|_| format!("invalid --sigma value {:?}", value))?;
+			}
+			"--format" => {
+				format = match value("--format")?.as_str() {
+					"tsv" => Format::Tsv,
+					"json" => Format::Json,
+					other => return Err(format!("unknown format {:?}", other)),
+				};
+			}
+			"-h" | "--help" => return Err(USAGE.to_string()),
+			flag if flag.starts_with("--") => return Err(format!("unknown flag {}", flag)),
+			_ => paths.push(PathBuf::from(arg)),
+		}
+	}
+
+	if paths.is_empty() {
+		return Err(USAGE.to_string());
+	}
+
+	Ok(Args {
+		paths,
+		config,
+		format,
+	})
+}
+
+/// `-` reads the whole of stdin and decodes it as an image.
+fn extract(path: &Path, config: &GradientConfig) -> Result<GradientResult, GradientError> {
+	if path != Path::new("-") {
+		return extract_gradient_hex_with_config(path, config);
+	}
+
+	let mut data = Vec::new();
+	std::io::stdin()
+		.read_to_end(&mut data)
+		.map_err(|source| GradientError::ReadFailed {
+			path: path.to_path_buf(),
+			source,
+		})?;
+	extract_gradient_hex_from_bytes_with_config(&data, config)
+}
+
+fn main() -> ExitCode {
+	let args = match parse_args() {
+		Ok(args) => args,
+		Err(message) => {
+			eprintln!("{}", message);
+			return ExitCode::from(2);
+		}
+	};
+
+	let mut failed = false;
+	for path in &args.paths {
+		let result = match extract(path, &args.config) {
+			Ok(result) => result,
+			Err(err) => {
+				eprintln!("{}: {}", path.display(), err);
+				failed = true;
+				continue;
+			}
+		};
+
+		match args.format {
+			Format::Tsv => println!(
+				"{}\t{}\t{}\t{:.2}",
+				path.display(),
+				result.start_hex(),
+				result.end_hex(),
+				result.angle
+			),
+			Format::Json => println!(
+				"{}",
+				serde_json::json!({
+					"path": path.display().to_string(),
+					"start": result.start_hex(),
+					"end": result.end_hex(),
+					"angle": result.angle,
+				})
+			),
+		}
+	}
+
+	if failed {
+		ExitCode::FAILURE
+	} else {
+		ExitCode::SUCCESS
+	}
+}
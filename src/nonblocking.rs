@@ -0,0 +1,24 @@
+use std::path::PathBuf;
+
+use crate::{GradientConfig, GradientResult, Result, extract_gradient_hex_with_config};
+
+pub async fn extract_gradient_hex_async(image_path: impl Into<PathBuf>) -> Result<GradientResult> {
+	extract_gradient_hex_async_with_config(image_path, GradientConfig::default()).await
+}
+
+/// Runs `extract_gradient_hex_with_config` on Tokio's blocking thread pool,
+/// so the extraction does not stall other tasks on the async runtime. A
+/// panic in the extraction is resumed in the awaiting task.
+pub async fn extract_gradient_hex_async_with_config(
+	image_path: impl Into<PathBuf>,
+	config: GradientConfig,
+) -> Result<GradientResult> {
+	let image_path = image_path.into();
+	let task =
+		tokio::task::spawn_blocking(move || extract_gradient_hex_with_config(&image_path, &config));
+	match task.await {
+		Ok(result) => result,
+		Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+		Err(err) => Err(err.into()),
+	}
+}
@@ -0,0 +1,557 @@
+use opencv::{
+	core::{self, Mat, Scalar},
+	imgcodecs,
+	prelude::*,
+};
+use std::path::Path;
+
+use crate::pipeline::{linear_to_srgb, srgb_to_linear};
+use crate::{GradientError, GradientResult, Result};
+
+/// Conventions `GradientResult::angle_in` can express the angle in. All of
+/// them describe the direction from the start color towards the end color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AngleConvention {
+	/// Degrees clockwise from the top of the image. This is `angle`.
+	Compass,
+	/// Degrees counter-clockwise from the positive x axis, with y pointing
+	/// up: `(90 - compass) mod 360`.
+	Math,
+	/// The angle of `linear-gradient(<angle>deg, ...)`: CSS measures the
+	/// `to` direction clockwise from `to top`, which equals `Compass`.
+	Css,
+}
+
+/// Casing and prefix of the strings returned by `start_hex_in` and
+/// `end_hex_in`. `LowerHash` matches `start_hex` and `end_hex`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HexStyle {
+	/// `#aabbcc`
+	#[default]
+	LowerHash,
+	/// `#AABBCC`
+	UpperHash,
+	/// `aabbcc`
+	LowerBare,
+	/// `AABBCC`
+	UpperBare,
+}
+
+impl GradientResult {
+	pub fn angle_in(&self, convention: AngleConvention) -> f64 {
+		match convention {
+			AngleConvention::Compass | AngleConvention::Css => self.angle,
+			AngleConvention::Math => (90.0 - self.angle).rem_euclid(360.0),
+		}
+	}
+
+	/// `angle` in radians, still clockwise from the top. `angle` is stored
+	/// unrounded, so this is exact up to the conversion itself.
+	pub fn angle_rad(&self) -> f64 {
+		self.angle.to_radians()
+	}
+
+	/// `angle` folded into `[0, 180)`, for when a gradient and its flip with
+	/// swapped colors count as the same direction.
+	pub fn angle_folded(&self) -> f64 {
+		self.angle.rem_euclid(180.0)
+	}
+
+	/// `start_rgb`, or `None` when its band was empty and `start_rgb` is only
+	/// a black placeholder.
+	pub fn sampled_start_rgb(&self) -> Option<[u8; 3]> {
+		(self.start_band_px > 0).then_some(self.start_rgb)
+	}
+
+	pub fn sampled_end_rgb(&self) -> Option<[u8; 3]> {
+		(self.end_band_px > 0).then_some(self.end_rgb)
+	}
+
+	pub fn start_hex(&self) -> String {
+		rgb_to_hex(self.start_rgb)
+	}
+
+	pub fn end_hex(&self) -> String {
+		rgb_to_hex(self.end_rgb)
+	}
+
+	/// Score in `[0, 1]`, 1.0 meaning the same gradient. It is
+	/// `0.7 * color + 0.3 * direction`, where `color` is `1 - mean ΔE / 100`
+	/// (CIE76, clamped at 0) over the start and end pairs and `direction` is
+	/// `1 - angle difference / 180`. A gradient and its 180 degree flip with
+	/// swapped colors are the same, so both pairings are tried and the higher
+	/// score wins.
+	pub fn similarity(&self, other: &GradientResult) -> f64 {
+		let score = |start: [u8; 3], end: [u8; 3], angle: f64| {
+			let delta_e = (delta_e(self.start_rgb, start) + delta_e(self.end_rgb, end)) / 2.0;
+			let color = (1.0 - delta_e / 100.0).max(0.0);
+			let diff = (self.angle - angle).rem_euclid(360.0);
+			let direction = 1.0 - diff.min(360.0 - diff) / 180.0;
+			0.7 * color + 0.3 * direction
+		};
+
+		let same = score(other.start_rgb, other.end_rgb, other.angle);
+		let flipped = score(other.end_rgb, other.start_rgb, other.angle + 180.0);
+		same.max(flipped)
+	}
+
+	/// Color `t` of the way from the start to the end color, with `t` clamped
+	/// to `[0, 1]`. Interpolates the sRGB bytes directly.
+	pub fn color_at(&self, t: f64) -> [u8; 3] {
+		let t = t.clamp(0.0, 1.0);
+		let mut rgb = [0; 3];
+		for ((value, &s), &e) in rgb.iter_mut().zip(&self.start_rgb).zip(&self.end_rgb) {
+			*value = (s as f64 + (e as f64 - s as f64) * t).round() as u8;
+		}
+		rgb
+	}
+
+	/// Start and end colors pulled `amount` of the way towards `mid_rgb`, for
+	/// a softer gradient. `amount` is clamped to `[0, 1]`; 1 gives `mid_rgb`
+	/// for both.
+	pub fn desaturate_toward_mean(&self, amount: f64) -> ([u8; 3], [u8; 3]) {
+		let amount = amount.clamp(0.0, 1.0);
+		let pull = |rgb: [u8; 3]| {
+			let mut pulled = [0; 3];
+			for ((value, &c), &m) in pulled.iter_mut().zip(&rgb).zip(&self.mid_rgb) {
+				*value = (c as f64 + (m as f64 - c as f64) * amount).round() as u8;
+			}
+			pulled
+		};
+		(pull(self.start_rgb), pull(self.end_rgb))
+	}
+
+	/// Like `color_at`, but interpolates in linear light, matching colors
+	/// extracted with `GradientConfig::linearize`.
+	pub fn color_at_linear(&self, t: f64) -> [u8; 3] {
+		let t = t.clamp(0.0, 1.0);
+		let mut rgb = [0; 3];
+		for ((value, &s), &e) in rgb.iter_mut().zip(&self.start_rgb).zip(&self.end_rgb) {
+			let (s, e) = (
+				srgb_to_linear(s as f32) as f64,
+				srgb_to_linear(e as f32) as f64,
+			);
+			*value = linear_to_srgb(s + (e - s) * t).round() as u8;
+		}
+		rgb
+	}
+
+	/// Hex of the sampled middle band, a representative single color for
+	/// the whole gradient.
+	pub fn midpoint_hex(&self) -> String {
+		rgb_to_hex(self.mid_rgb)
+	}
+
+	/// `#rgb` shorthand of `start_hex` when it is lossless, the full form
+	/// otherwise.
+	pub fn start_short_hex(&self) -> String {
+		Self::to_short_hex(&self.start_hex())
+	}
+
+	pub fn end_short_hex(&self) -> String {
+		Self::to_short_hex(&self.end_hex())
+	}
+
+	/// Collapses `#aabbcc` to `#abc` when both digits of every channel are
+	/// equal. Keeps the `#` prefix and casing of the input, and returns
+	/// strings that are not six-digit hex colors unchanged.
+	pub fn to_short_hex(color: &str) -> String {
+		let (prefix, digits) = match color.strip_prefix('#') {
+			Some(digits) => ("#", digits),
+			None => ("", color),
+		};
+		if parse_hex(digits).is_none() {
+			return color.to_string();
+		}
+
+		let bytes = digits.as_bytes();
+		if bytes[0] == bytes[1] && bytes[2] == bytes[3] && bytes[4] == bytes[5] {
+			format!(
+				"{}{}{}{}",
+				prefix, bytes[0] as char, bytes[2] as char, bytes[4] as char
+			)
+		} else {
+			color.to_string()
+		}
+	}
+
+	/// `#rrrrggggbbbb`, four lowercase hex digits per channel.
+	pub fn start_hex16(&self) -> String {
+		rgb16_to_hex(self.start_rgb16)
+	}
+
+	pub fn end_hex16(&self) -> String {
+		rgb16_to_hex(self.end_rgb16)
+	}
+
+	pub fn start_hex_in(&self, style: HexStyle) -> String {
+		styled_hex(self.start_rgb, style)
+	}
+
+	pub fn end_hex_in(&self, style: HexStyle) -> String {
+		styled_hex(self.end_rgb, style)
+	}
+
+	/// Hue in degrees, saturation and lightness in `[0, 1]`. Hue is rounded to
+	/// 0.1 degrees, saturation and lightness to three decimals.
+	pub fn start_hsl(&self) -> (f64, f64, f64) {
+		rgb_to_hsl(self.start_rgb)
+	}
+
+	pub fn end_hsl(&self) -> (f64, f64, f64) {
+		rgb_to_hsl(self.end_rgb)
+	}
+
+	#[cfg(feature = "palette")]
+	pub fn start_srgb(&self) -> palette::Srgb<u8> {
+		let [r, g, b] = self.start_rgb;
+		palette::Srgb::new(r, g, b)
+	}
+
+	#[cfg(feature = "palette")]
+	pub fn end_srgb(&self) -> palette::Srgb<u8> {
+		let [r, g, b] = self.end_rgb;
+		palette::Srgb::new(r, g, b)
+	}
+
+	/// CIELAB with a D65 white point, as used by `similarity`.
+	#[cfg(feature = "palette")]
+	pub fn start_lab(&self) -> palette::Lab {
+		use palette::IntoColor;
+		self.start_srgb().into_format::<f32>().into_color()
+	}
+
+	#[cfg(feature = "palette")]
+	pub fn end_lab(&self) -> palette::Lab {
+		use palette::IntoColor;
+		self.end_srgb().into_format::<f32>().into_color()
+	}
+
+	/// Correlated color temperature of `mid_rgb` in kelvin, from McCamy's
+	/// approximation on its CIE 1931 chromaticity: warm gradients give low
+	/// values, cool ones high. Only meaningful for near-white colors roughly
+	/// between 2000 K and 12500 K; black gives NaN.
+	pub fn color_temperature_k(&self) -> f64 {
+		let [r, g, b] = self.mid_rgb.map(|c| srgb_to_linear(c as f32) as f64);
+		let x = 0.4124 * r + 0.3576 * g + 0.1805 * b;
+		let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+		let z = 0.0193 * r + 0.1192 * g + 0.9505 * b;
+		let sum = x + y + z;
+		let (cx, cy) = (x / sum, y / sum);
+
+		let n = (cx - 0.3320) / (0.1858 - cy);
+		449.0 * n.powi(3) + 3525.0 * n.powi(2) + 6823.3 * n + 5520.33
+	}
+
+	/// `angle` already follows the CSS convention (0deg points to the top,
+	/// increasing clockwise, towards the end color), so it is used as is.
+	pub fn to_css(&self) -> String {
+		format!(
+			"linear-gradient({:.1}deg, {}, {})",
+			self.angle,
+			self.start_hex(),
+			self.end_hex()
+		)
+	}
+
+	/// Tailwind classes with arbitrary-value colors. Tailwind only has eight
+	/// directions, so `angle` is rounded to the nearest multiple of 45
+	/// degrees: 0 is `to-t`, 45 `to-tr`, 90 `to-r` and so on clockwise to
+	/// 315, `to-tl`.
+	pub fn to_tailwind(&self) -> String {
+		const DIRECTIONS: [&str; 8] = ["t", "tr", "r", "br", "b", "bl", "l", "tl"];
+		let direction = DIRECTIONS[(self.angle / 45.0).round().rem_euclid(8.0) as usize];
+		format!(
+			"bg-gradient-to-{} from-[{}] to-[{}]",
+			direction,
+			self.start_hex(),
+			self.end_hex()
+		)
+	}
+
+	/// The gradient vector is centred in the unit square and stretched until
+	/// it touches the square's edges.
+	pub fn to_svg_def(&self, id: &str) -> String {
+		let (x1, y1, x2, y2) = unit_square_endpoints(self.angle);
+		format!(
+			"<linearGradient id=\"{}\" x1=\"{:.4}\" y1=\"{:.4}\" x2=\"{:.4}\" y2=\"{:.4}\">\
+			<stop offset=\"0\" stop-color=\"{}\"/>\
+			<stop offset=\"1\" stop-color=\"{}\"/>\
+			</linearGradient>",
+			id,
+			x1,
+			y1,
+			x2,
+			y2,
+			self.start_hex(),
+			self.end_hex()
+		)
+	}
+
+	/// Begin and end are the SVG unit-square endpoints mapped onto Flutter's
+	/// `[-1, 1]` alignment square.
+	pub fn to_flutter(&self) -> String {
+		let (x1, y1, x2, y2) = unit_square_endpoints(self.angle);
+		let align = |v: f64| 2.0 * v - 1.0;
+		format!(
+			"LinearGradient(begin: Alignment({:.3}, {:.3}), end: Alignment({:.3}, {:.3}), colors: [{}, {}])",
+			align(x1),
+			align(y1),
+			align(x2),
+			align(y2),
+			flutter_color(self.start_rgb),
+			flutter_color(self.end_rgb)
+		)
+	}
+
+	/// Writes the gradient as a single-segment GIMP `.ggr` file named
+	/// `name`. See `write_ggr_stops` for multi-stop gradients.
+	pub fn write_ggr(&self, name: &str, out: &Path) -> Result<()> {
+		write_ggr_stops(&[(0.0, self.start_rgb), (1.0, self.end_rgb)], name, out)
+	}
+
+	/// Writes a `width` x `height` linear gradient from the start to the end
+	/// color along `angle`, for comparing the detection with the original.
+	/// The colors are interpolated over the projection `x * dx + y * dy`
+	/// used during extraction.
+	pub fn render_to(&self, width: i32, height: i32, out: &Path) -> Result<()> {
+		let image = render_linear(width, height, self.angle, self.start_rgb, self.end_rgb)?;
+		let out_str = out
+			.to_str()
+			.ok_or_else(|| GradientError::InvalidPath(out.to_path_buf()))?;
+
+		if !imgcodecs::imwrite(out_str, &image, &core::Vector::new())? {
+			return Err(GradientError::UnsupportedFormat(format!(
+				"Could not write {}",
+				out.display()
+			)));
+		}
+		Ok(())
+	}
+}
+
+/// BGR image of the linear gradient drawn by `GradientResult::render_to`.
+pub(crate) fn render_linear(
+	width: i32,
+	height: i32,
+	angle: f64,
+	start_rgb: [u8; 3],
+	end_rgb: [u8; 3],
+) -> Result<Mat> {
+	if width <= 0 || height <= 0 {
+		return Err(GradientError::InvalidArgument(format!(
+			"Render size must be positive, got {}x{}",
+			width, height
+		)));
+	}
+
+	let rad = angle.to_radians();
+	let (dx, dy) = (rad.sin(), -rad.cos());
+	let corners = [
+		(0, 0),
+		(width - 1, 0),
+		(0, height - 1),
+		(width - 1, height - 1),
+	]
+	.map(|(x, y)| x as f64 * dx + y as f64 * dy);
+	let t_min = corners.iter().copied().fold(f64::INFINITY, f64::min);
+	let t_max = corners.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+	let span = (t_max - t_min).max(f64::EPSILON);
+
+	let [start, end] = [start_rgb, end_rgb].map(|[r, g, b]| [b, g, r]);
+	let mut image =
+		Mat::new_rows_cols_with_default(height, width, core::CV_8UC3, Scalar::all(0.0))?;
+	for y in 0..height {
+		let row = image.at_row_mut::<core::Vec3b>(y)?;
+		for (x, pixel) in row.iter_mut().enumerate() {
+			let f = (x as f64 * dx + y as f64 * dy - t_min) / span;
+			let mut bgr = [0u8; 3];
+			for (c, value) in bgr.iter_mut().enumerate() {
+				let (s, e) = (start[c] as f64, end[c] as f64);
+				*value = (s + (e - s) * f).round() as u8;
+			}
+			*pixel = core::Vec3b::from(bgr);
+		}
+	}
+	Ok(image)
+}
+
+fn flutter_color(rgb: [u8; 3]) -> String {
+	format!("Color(0xFF{:02X}{:02X}{:02X})", rgb[0], rgb[1], rgb[2])
+}
+
+fn unit_square_endpoints(angle: f64) -> (f64, f64, f64, f64) {
+	let rad = angle.to_radians();
+	let (vx, vy) = (rad.sin(), -rad.cos());
+	let scale = 0.5 / vx.abs().max(vy.abs());
+	let (hx, hy) = (vx * scale, vy * scale);
+	(0.5 - hx, 0.5 - hy, 0.5 + hx, 0.5 + hy)
+}
+
+pub(crate) fn delta_e(a: [u8; 3], b: [u8; 3]) -> f64 {
+	let (a, b) = (rgb_to_lab(a), rgb_to_lab(b));
+	((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+/// CIELAB chroma, the distance from the neutral axis.
+pub(crate) fn chroma(rgb: [u8; 3]) -> f64 {
+	let [_, a, b] = rgb_to_lab(rgb);
+	a.hypot(b)
+}
+
+/// CIELAB with a D65 white point.
+fn rgb_to_lab(rgb: [u8; 3]) -> [f64; 3] {
+	let [r, g, b] = rgb.map(|c| srgb_to_linear(c as f32) as f64);
+	let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+	let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+	let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+	let f = |t: f64| {
+		if t > 216.0 / 24389.0 {
+			t.cbrt()
+		} else {
+			(24389.0 / 27.0 * t + 16.0) / 116.0
+		}
+	};
+	let (fx, fy, fz) = (f(x), f(y), f(z));
+	[116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn rgb_to_hsl(rgb: [u8; 3]) -> (f64, f64, f64) {
+	let [r, g, b] = rgb.map(|c| c as f64 / 255.0);
+	let max = r.max(g).max(b);
+	let min = r.min(g).min(b);
+	let delta = max - min;
+	let l = (max + min) / 2.0;
+
+	let (h, s) = if delta == 0.0 {
+		(0.0, 0.0)
+	} else {
+		let s = delta / (1.0 - (2.0 * l - 1.0).abs());
+		let h = if max == r {
+			((g - b) / delta).rem_euclid(6.0)
+		} else if max == g {
+			(b - r) / delta + 2.0
+		} else {
+			(r - g) / delta + 4.0
+		};
+		(h * 60.0, s)
+	};
+
+	(
+		(h * 10.0).round() / 10.0 % 360.0,
+		(s * 1000.0).round() / 1000.0,
+		(l * 1000.0).round() / 1000.0,
+	)
+}
+
+fn styled_hex(rgb: [u8; 3], style: HexStyle) -> String {
+	let [r, g, b] = rgb;
+	match style {
+		HexStyle::LowerHash => format!("#{:02x}{:02x}{:02x}", r, g, b),
+		HexStyle::UpperHash => format!("#{:02X}{:02X}{:02X}", r, g, b),
+		HexStyle::LowerBare => format!("{:02x}{:02x}{:02x}", r, g, b),
+		HexStyle::UpperBare => format!("{:02X}{:02X}{:02X}", r, g, b),
+	}
+}
+
+fn rgb16_to_hex(rgb: [u16; 3]) -> String {
+	format!("#{:04x}{:04x}{:04x}", rgb[0], rgb[1], rgb[2])
+}
+
+/// Writes `stops`, as returned by `extract_gradient_stops`, as a GIMP `.ggr`
+/// file with one linearly blended RGB segment between each pair of
+/// neighbouring stops. Stops must be sorted by position.
+pub fn write_ggr_stops(stops: &[(f64, [u8; 3])], name: &str, out: &Path) -> Result<()> {
+	if stops.len() < 2 {
+		return Err(GradientError::InvalidArgument(
+			"A .ggr file needs at least two stops".to_string(),
+		));
+	}
+
+	let channels = |rgb: [u8; 3]| rgb.map(|c| format!("{:.6}", c as f64 / 255.0)).join(" ");
+	let mut ggr = format!(
+		"GIMP Gradient\nName: {}\n{}\n",
+		name.replace('\n', " "),
+		stops.len() - 1
+	);
+	for pair in stops.windows(2) {
+		let ((left, left_rgb), (right, right_rgb)) = (pair[0], pair[1]);
+		ggr += &format!(
+			"{:.6} {:.6} {:.6} {} 1.000000 {} 1.000000 0 0\n",
+			left,
+			(left + right) / 2.0,
+			right,
+			channels(left_rgb),
+			channels(right_rgb)
+		);
+	}
+
+	std::fs::write(out, ggr).map_err(|source| GradientError::WriteFailed {
+		path: out.to_path_buf(),
+		source,
+	})
+}
+
+/// `#aabbcc -> #112233 @ 143.2°`: the start and end colors and the angle in
+/// compass degrees.
+impl std::fmt::Display for GradientResult {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"{} -> {} @ {:.1}°",
+			self.start_hex(),
+			self.end_hex(),
+			self.angle
+		)
+	}
+}
+
+fn rgb_to_hex(rgb: [u8; 3]) -> String {
+	format!("#{:02x}{:02x}{:02x}", rgb[0], rgb[1], rgb[2])
+}
+
+fn parse_hex(hex: &str) -> Option<[u8; 3]> {
+	let digits = hex.strip_prefix('#').unwrap_or(hex);
+	if digits.len() != 6 || !digits.is_ascii() {
+		return None;
+	}
+
+	let channel = |i: usize| u8::from_str_radix(&digits[i..i + 2], 16).ok();
+	Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+#[cfg(feature = "serde")]
+pub(crate) mod color_hex {
+	use opencv::{prelude::*, viz::Color};
+	use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+	pub fn serialize<S: Serializer>(color: &Color, serializer: S) -> Result<S::Ok, S::Error> {
+		let bgr = color.to_vec3b().map_err(serde::ser::Error::custom)?;
+		serializer.serialize_str(&super::rgb_to_hex([bgr[2], bgr[1], bgr[0]]))
+	}
+
+	pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+		let hex = String::deserialize(deserializer)?;
+		let rgb = super::parse_hex(&hex)
+			.ok_or_else(|| D::Error::custom(format!("Invalid hex color {:?}", hex)))?;
+		Color::new_1(rgb[2] as f64, rgb[1] as f64, rgb[0] as f64).map_err(D::Error::custom)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::extract_gradient_from_mat;
+
+	#[test]
+	fn display_shows_hex_and_angle() -> Result<()> {
+		let ramp = render_linear(64, 64, 90.0, [0, 0, 0], [255, 255, 255])?;
+		let mut result = extract_gradient_from_mat(&ramp)?;
+		result.start_rgb = [0xaa, 0xbb, 0xcc];
+		result.end_rgb = [0x11, 0x22, 0x33];
+		result.angle = 143.24;
+		assert_eq!(result.to_string(), "#aabbcc -> #112233 @ 143.2°");
+		Ok(())
+	}
+}
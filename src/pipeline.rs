@@ -0,0 +1,2406 @@
+use opencv::core::CV_32F;
+use opencv::{
+	core::{self, BorderTypes, CV_64F, Mat, Scalar},
+	imgcodecs, imgproc,
+	prelude::*,
+	viz::Color,
+};
+use std::path::Path;
+use std::time::Instant;
+
+use crate::angle::{
+	DominantDirection, GradientField, angle_histogram, axis_snapped_angle, field_direction,
+	gradient_field, gray_image, is_multimodal, snap_angle,
+};
+use crate::kind::{self, ConicInfo, GradientKind, RadialInfo};
+use crate::output::{chroma, delta_e};
+use crate::timing::Timing;
+use crate::{
+	ColorAverageSpace, ColorStatistic, GradientConfig, GradientError, Orientation, ResizeStrategy,
+	Result, RoundingMode, StopSelection, compass_angle,
+};
+
+/// Extraction is deterministic: the same image and config give bit-identical
+/// results across runs and thread counts, with or without `rayon`, for a
+/// given OpenCV build. Different OpenCV versions or SIMD paths may still
+/// differ in the last bits of the blur. `tests/golden.rs` pins the output
+/// on a few fixtures.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GradientResult {
+	#[cfg_attr(feature = "serde", serde(with = "crate::output::color_hex"))]
+	pub start_color: Color,
+	#[cfg_attr(feature = "serde", serde(with = "crate::output::color_hex"))]
+	pub end_color: Color,
+	pub start_rgb: [u8; 3],
+	pub end_rgb: [u8; 3],
+	/// The end colors at 16 bits per channel. They only carry more than the
+	/// 8-bit colors with `GradientConfig::high_bit_depth` (or Lab averaging).
+	pub start_rgb16: [u16; 3],
+	pub end_rgb16: [u16; 3],
+	/// Color of the band between the start and end bands, or the halfway
+	/// mix of the end colors when that band is empty.
+	pub mid_rgb: [u8; 3],
+	/// Compass degrees from the start towards the end color. Set to the
+	/// exact axis when the directions agree almost perfectly and lie within
+	/// a tenth of a degree of one, then snapped by `GradientConfig::snap_angle`.
+	/// 0 when `kind` is `GradientKind::Flat`.
+	pub angle: f64,
+	/// The detected angle before any snapping, neither to the exact axis
+	/// nor by `GradientConfig::snap_angle`. Also 0 for flat images.
+	pub raw_angle: f64,
+	pub confidence: f64,
+	pub start_point: Option<(i32, i32)>,
+	pub end_point: Option<(i32, i32)>,
+	/// Pixels the start and end colors were sampled from. Colors from a
+	/// band of only a few pixels are unreliable; with none, the color is a
+	/// black placeholder (see `GradientResult::sampled_start_rgb`).
+	pub start_band_px: i32,
+	pub end_band_px: i32,
+	/// Range of the projection `x * dx + y * dy` over the analysed pixels of
+	/// the resized image, leaving out masked, excluded and transparent ones;
+	/// the end bands are `band_frac` of it from either side.
+	pub t_min: f64,
+	pub t_max: f64,
+	pub kind: GradientKind,
+	pub radial: Option<RadialInfo>,
+	pub conic: Option<ConicInfo>,
+	/// Mean absolute per-channel difference, in 8-bit levels, between the
+	/// blurred image and the reconstructed two-stop gradient. Only computed
+	/// with `GradientConfig::compute_fit_error`.
+	pub fit_error: Option<f64>,
+	/// Set when the edge directions form two strong, well separated peaks, in
+	/// which case `angle` is an average of both and should not be trusted.
+	pub multimodal: bool,
+	/// Set when a channel of the start or end color fell outside `[0, 255]`
+	/// before rounding, which can happen when converting back from Lab.
+	pub clamped: bool,
+	/// Set when the start and end colors are too close to tell apart. The
+	/// start is whichever end the direction axis happens to point away from,
+	/// so on such near-symmetric images start and end may as well be swapped
+	/// and the angle flipped by 180 degrees.
+	pub direction_ambiguous: bool,
+	/// Set when the start, middle and end colors are all close to neutral
+	/// gray, so the gradient is a lightness ramp without a meaningful hue.
+	pub is_grayscale: bool,
+	/// Experimental: the angle estimated separately on the red, green and
+	/// blue channels, with `GradientConfig::per_channel_angles`. Channels
+	/// that disagree with each other and with `raw_angle` point to a hue
+	/// shift that the grayscale estimate averages away. Like `raw_angle`
+	/// these are not snapped, and each is only defined up to 180 degrees.
+	pub per_channel_angles: Option<[f64; 3]>,
+	/// Set when the decoded image has from 3 to `PALETTE_MAX_COLORS` distinct
+	/// colors, as old indexed PNGs and GIFs often do; solid and two-color
+	/// images are not counted. Such images are
+	/// median-filtered first when they look dithered, and their end colors
+	/// are palette entries or mixes of them rather than the intended ones.
+	pub palette_limited: bool,
+}
+
+/// A band color rounded to 8 bits, together with its 16-bit rounding and
+/// whether a channel fell outside `[0, 255]` and had to be clamped.
+#[derive(Clone, Copy)]
+pub(crate) struct BandColor {
+	pub(crate) bgr: core::Vec3d,
+	pub(crate) bgr16: [u16; 3],
+	pub(crate) clamped: bool,
+}
+
+pub(crate) struct Analysis {
+	pub(crate) blurred: Mat,
+	pub(crate) lab: Option<Mat>,
+	pub(crate) linear: Option<Mat>,
+	pub(crate) opaque: Option<Mat>,
+	pub(crate) field: GradientField,
+	pub(crate) direction: DominantDirection,
+	pub(crate) t: Mat,
+	pub(crate) t_min: f64,
+	pub(crate) t_max: f64,
+	pub(crate) statistic: ColorStatistic,
+	pub(crate) rounding: RoundingMode,
+	pub(crate) selection: StopSelection,
+	pub(crate) channel_angles: Option<[f64; 3]>,
+	pub(crate) palette_limited: bool,
+}
+
+impl Analysis {
+	pub(crate) fn band_mask(&self, low: f64, high: f64) -> Result<Mat> {
+		self.restrict(band_mask(&self.t, low, high)?)
+	}
+
+	pub(crate) fn restrict(&self, mask: Mat) -> Result<Mat> {
+		match &self.opaque {
+			Some(opaque) => {
+				let mut masked = Mat::default();
+				core::bitwise_and(&mask, opaque, &mut masked, &core::no_array())?;
+				Ok(masked)
+			}
+			None => Ok(mask),
+		}
+	}
+
+	pub(crate) fn band_color(&self, mask: &Mat) -> Result<Option<core::Vec3d>> {
+		Ok(self.quantized_band_color(mask)?.map(|color| color.bgr))
+	}
+
+	/// Like `band_color`, but also keeps the 16-bit precision and whether
+	/// the color was clamped.
+	pub(crate) fn quantized_band_color(&self, mask: &Mat) -> Result<Option<BandColor>> {
+		sampled_band_color(
+			&self.blurred,
+			self.lab.as_ref(),
+			self.linear.as_ref(),
+			mask,
+			self.statistic,
+			self.selection,
+			self.rounding,
+		)
+	}
+}
+
+/// The color of the `mask` pixels of `image` as the extraction samples it,
+/// averaged over `lab` or `linear` instead when given, or `None` for an
+/// empty mask.
+fn sampled_band_color(
+	image: &Mat,
+	lab: Option<&Mat>,
+	linear: Option<&Mat>,
+	mask: &Mat,
+	statistic: ColorStatistic,
+	selection: StopSelection,
+	rounding: RoundingMode,
+) -> Result<Option<BandColor>> {
+	if core::count_non_zero(mask)? == 0 {
+		return Ok(None);
+	}
+
+	let source = lab.or(linear).unwrap_or(image);
+	let raw = match selection {
+		StopSelection::Mean => band_statistic(source, mask, statistic)?,
+		StopSelection::KMeans { k } => dominant_cluster(source, mask, k)?,
+	};
+
+	let value = match (lab, linear) {
+		(Some(_), _) => lab_to_bgr(raw)?,
+		(None, Some(_)) => raw.map(linear_to_srgb),
+		(None, None) => raw,
+	};
+	Ok(Some(quantize(value, rounding)))
+}
+
+/// Intermediate buffers kept by `GradientExtractor` between calls. OpenCV
+/// writes into an output Mat's existing allocation when its size and type
+/// already match, so same-sized inputs stop reallocating after the first.
+#[derive(Default)]
+pub(crate) struct Scratch {
+	pub(crate) data: Vec<u8>,
+	/// The resized image before blurring.
+	pub(crate) small: Mat,
+	blurred: Mat,
+	gray: Mat,
+	grad_x: Mat,
+	grad_y: Mat,
+	/// Stage timings of the last `analyze` call; decode and colors are
+	/// left zero.
+	pub(crate) timing: Timing,
+}
+
+pub fn extract_gradient_hex(
+	image_path: &Path,
+	resize_width: i32,
+	blur_sigma: f64,
+) -> Result<GradientResult> {
+	let config = GradientConfig {
+		resize_strategy: ResizeStrategy::FixedWidth(resize_width),
+		blur_sigma,
+		..GradientConfig::default()
+	};
+	extract_gradient_hex_with_config(image_path, &config)
+}
+
+pub fn extract_gradient_hex_with_config(
+	image_path: &Path,
+	config: &GradientConfig,
+) -> Result<GradientResult> {
+	let img = read_image(image_path, config)?;
+	extract_gradient_from_mat_with_config(&img, config)
+}
+
+pub fn try_extract_gradient(image_path: &Path) -> Result<Option<GradientResult>> {
+	try_extract_gradient_with_config(image_path, &GradientConfig::default())
+}
+
+/// Like `extract_gradient_hex_with_config`, but returns `None` instead of an
+/// arbitrary result when the image holds too few gradient pixels or its
+/// `confidence` is below `config.min_coherence`.
+pub fn try_extract_gradient_with_config(
+	image_path: &Path,
+	config: &GradientConfig,
+) -> Result<Option<GradientResult>> {
+	let result = extract_gradient_hex_with_config(image_path, config)?;
+	if result.kind == GradientKind::Flat || result.confidence < config.min_coherence {
+		return Ok(None);
+	}
+
+	Ok(Some(result))
+}
+
+pub fn extract_gradient_hex_from_bytes(
+	data: &[u8],
+	resize_width: i32,
+	blur_sigma: f64,
+) -> Result<GradientResult> {
+	let config = GradientConfig {
+		resize_strategy: ResizeStrategy::FixedWidth(resize_width),
+		blur_sigma,
+		..GradientConfig::default()
+	};
+	extract_gradient_hex_from_bytes_with_config(data, &config)
+}
+
+/// Decodes `data` with the linked OpenCV's `imgcodecs`, falling back to the
+/// `image` crate when the `image` feature is enabled. Formats neither can
+/// read fail with `GradientError::UnsupportedFormat`.
+pub fn extract_gradient_hex_from_bytes_with_config(
+	data: &[u8],
+	config: &GradientConfig,
+) -> Result<GradientResult> {
+	config.validate()?;
+	let img = decode_image(data, config)?;
+	extract_gradient_from_mat_with_config(&img, config)
+}
+
+pub fn extract_gradient_from_mat(img: &Mat) -> Result<GradientResult> {
+	extract_gradient_from_mat_with_config(img, &GradientConfig::default())
+}
+
+/// Runs the extraction on an already decoded image in OpenCV's BGR order.
+/// Gray, BGR and BGRA Mats of 8-bit, 16-bit or float depth are accepted and
+/// converted; anything else fails with `GradientError::UnsupportedFormat`.
+pub fn extract_gradient_from_mat_with_config(
+	img: &Mat,
+	config: &GradientConfig,
+) -> Result<GradientResult> {
+	if img.empty() {
+		return Err(GradientError::EmptyImage);
+	}
+	extract_gradient(img, config, &mut Scratch::default())
+}
+
+/// Like `extract_gradient_hex_with_config`, limited to the non-zero pixels
+/// of `mask`, a single-channel Mat the size of the image. The mask is
+/// shrunk along with the image; pixels it at least half covers count. It is
+/// combined with `config.roi`, `border_exclude_frac` and any alpha channel.
+pub fn extract_gradient_with_mask(
+	image_path: &Path,
+	mask: &Mat,
+	config: &GradientConfig,
+) -> Result<GradientResult> {
+	let img = read_image(image_path, config)?;
+	let analysis = analyze_masked(&img, Some(mask), config, &mut Scratch::default())?;
+	gradient_from_analysis(&analysis, config)
+}
+
+pub fn extract_gradient_frames(image_path: &Path) -> Result<Vec<GradientResult>> {
+	extract_gradient_frames_with_config(image_path, &GradientConfig::default())
+}
+
+/// Runs the extraction on every frame of an animated or multi-page image,
+/// in order. Static images give a single result. Animated GIF and WebP
+/// the linked OpenCV cannot split into frames need the `image` feature.
+pub fn extract_gradient_frames_with_config(
+	image_path: &Path,
+	config: &GradientConfig,
+) -> Result<Vec<GradientResult>> {
+	config.validate()?;
+	let data = read_file(image_path)?;
+	let frames = decode_frames(&data, config)?;
+
+	let mut scratch = Scratch::default();
+	frames
+		.iter()
+		.map(|frame| extract_gradient(frame, config, &mut scratch))
+		.collect()
+}
+
+#[cfg(feature = "image")]
+pub fn extract_gradient_from_rgb(img: &image::RgbImage) -> Result<GradientResult> {
+	extract_gradient_from_rgb_with_config(img, &GradientConfig::default())
+}
+
+#[cfg(feature = "image")]
+pub fn extract_gradient_from_rgb_with_config(
+	img: &image::RgbImage,
+	config: &GradientConfig,
+) -> Result<GradientResult> {
+	let bgr = rgb_to_bgr(img)?;
+	extract_gradient_from_mat_with_config(&bgr, config)
+}
+
+#[cfg(feature = "image")]
+fn rgb_to_bgr(img: &image::RgbImage) -> Result<Mat> {
+	let (width, height) = img.dimensions();
+	if width == 0 || height == 0 {
+		return Err(GradientError::EmptyImage);
+	}
+
+	let rgb =
+		Mat::new_rows_cols_with_bytes::<core::Vec3b>(height as i32, width as i32, img.as_raw())?;
+	let mut bgr = Mat::default();
+	imgproc::cvt_color(
+		&rgb,
+		&mut bgr,
+		imgproc::COLOR_RGB2BGR,
+		0,
+		core::AlgorithmHint::ALGO_HINT_DEFAULT,
+	)?;
+
+	Ok(bgr)
+}
+
+#[cfg(feature = "image")]
+fn rgba_to_bgra(img: &image::RgbaImage) -> Result<Mat> {
+	let (width, height) = img.dimensions();
+	if width == 0 || height == 0 {
+		return Err(GradientError::EmptyImage);
+	}
+
+	let rgba =
+		Mat::new_rows_cols_with_bytes::<core::Vec4b>(height as i32, width as i32, img.as_raw())?;
+	let mut bgra = Mat::default();
+	imgproc::cvt_color(
+		&rgba,
+		&mut bgra,
+		imgproc::COLOR_RGBA2BGRA,
+		0,
+		core::AlgorithmHint::ALGO_HINT_DEFAULT,
+	)?;
+
+	Ok(bgra)
+}
+
+pub fn extract_gradient_stops(image_path: &Path, n: usize) -> Result<Vec<(f64, [u8; 3])>> {
+	extract_gradient_stops_with_config(image_path, n, &GradientConfig::default())
+}
+
+pub fn extract_gradient_stops_with_config(
+	image_path: &Path,
+	n: usize,
+	config: &GradientConfig,
+) -> Result<Vec<(f64, [u8; 3])>> {
+	if n == 0 {
+		return Err(GradientError::InvalidArgument(
+			"At least one stop is required".to_string(),
+		));
+	}
+
+	let img = read_image(image_path, config)?;
+	let analysis = analyze(&img, config, &mut Scratch::default())?;
+	color_profile(&analysis, n)
+}
+
+pub fn detect_stop_count(image_path: &Path) -> Result<usize> {
+	detect_stop_count_with_config(image_path, &GradientConfig::default())
+}
+
+/// Number of stops, at least 2, that describe the gradient. The colors of
+/// `PROFILE_BINS` bins along `t` are approximated by a polyline with
+/// Douglas-Peucker splitting: a segment is split at its worst bin while some
+/// bin is more than `PROFILE_TOLERANCE` RGB levels from the straight
+/// interpolation between the segment's ends. Every split adds a stop.
+pub fn detect_stop_count_with_config(image_path: &Path, config: &GradientConfig) -> Result<usize> {
+	let img = read_image(image_path, config)?;
+	let analysis = analyze(&img, config, &mut Scratch::default())?;
+	let profile = color_profile(&analysis, PROFILE_BINS)?;
+	Ok(2 + profile_splits(&profile, 0, profile.len() - 1))
+}
+
+const PROFILE_BINS: usize = 32;
+const PROFILE_TOLERANCE: f64 = 8.0;
+
+fn profile_splits(profile: &[(f64, [u8; 3])], first: usize, last: usize) -> usize {
+	let (p0, c0) = profile[first];
+	let (p1, c1) = profile[last];
+	let worst = (first + 1..last)
+		.map(|i| {
+			let (p, c) = profile[i];
+			let f = (p - p0) / (p1 - p0);
+			let distance = (0..3)
+				.map(|ch| {
+					let expected = c0[ch] as f64 + (c1[ch] as f64 - c0[ch] as f64) * f;
+					(c[ch] as f64 - expected).powi(2)
+				})
+				.sum::<f64>()
+				.sqrt();
+			(i, distance)
+		})
+		.max_by(|a, b| a.1.total_cmp(&b.1));
+
+	match worst {
+		Some((i, distance)) if distance > PROFILE_TOLERANCE => {
+			1 + profile_splits(profile, first, i) + profile_splits(profile, i, last)
+		}
+		_ => 0,
+	}
+}
+
+/// Colors of `n` equal bins along `t`, at the bins' centre positions in
+/// `[0, 1]`. Empty bins are interpolated from their neighbours.
+fn color_profile(analysis: &Analysis, n: usize) -> Result<Vec<(f64, [u8; 3])>> {
+	let (min_val, max_val) = (analysis.t_min, analysis.t_max);
+
+	let bin_width = (max_val - min_val) / n as f64;
+	let mut samples = Vec::with_capacity(n);
+	for i in 0..n {
+		let low = if i == 0 {
+			f64::NEG_INFINITY
+		} else {
+			min_val + i as f64 * bin_width
+		};
+		let high = if i == n - 1 {
+			f64::INFINITY
+		} else {
+			min_val + (i + 1) as f64 * bin_width
+		};
+
+		let mask = analysis.band_mask(low, high)?;
+
+		let position = (i as f64 + 0.5) / n as f64;
+		let bgr = analysis.band_color(&mask)?;
+		samples.push((position, bgr));
+	}
+
+	let stops = (0..n)
+		.map(|i| {
+			let (position, bgr) = samples[i];
+			let bgr = bgr.unwrap_or_else(|| {
+				let prev = samples[..i]
+					.iter()
+					.rev()
+					.find_map(|&(p, c)| c.map(|c| (p, c)));
+				let next = samples[i + 1..]
+					.iter()
+					.find_map(|&(p, c)| c.map(|c| (p, c)));
+				match (prev, next) {
+					(Some((p0, c0)), Some((p1, c1))) => {
+						let f = (position - p0) / (p1 - p0);
+						core::Vec3d::from([
+							(c0[0] + (c1[0] - c0[0]) * f).round(),
+							(c0[1] + (c1[1] - c0[1]) * f).round(),
+							(c0[2] + (c1[2] - c0[2]) * f).round(),
+						])
+					}
+					(Some((_, c)), None) | (None, Some((_, c))) => c,
+					(None, None) => core::Vec3d::all(0.0),
+				}
+			});
+			(position, bgr_to_rgb(bgr))
+		})
+		.collect();
+
+	Ok(stops)
+}
+
+pub fn extract_angle_histogram(image_path: &Path, bins: usize) -> Result<Vec<u32>> {
+	extract_angle_histogram_with_config(image_path, bins, &GradientConfig::default())
+}
+
+/// Histogram of the edge directions that feed the angle estimate. Bin `i`
+/// counts the pixels whose compass direction, folded to `[0, 180)`, lies in
+/// `[i * 180 / bins, (i + 1) * 180 / bins)`.
+pub fn extract_angle_histogram_with_config(
+	image_path: &Path,
+	bins: usize,
+	config: &GradientConfig,
+) -> Result<Vec<u32>> {
+	let img = read_image(image_path, config)?;
+	let analysis = analyze(&img, config, &mut Scratch::default())?;
+	angle_histogram(&analysis.field, bins)
+}
+
+/// Returns the mean RGB color of the `image` pixels whose projection `t`
+/// lies within `[low, high]`, or black when no pixel does.
+pub fn sample_band_color(image: &Mat, t: &Mat, low: f64, high: f64) -> Result<[u8; 3]> {
+	sample_band_color_with_config(image, t, low, high, &GradientConfig::default())
+}
+
+/// Like `sample_band_color`, but samples the band the way the extraction
+/// does with `config`: its `average_space`, `linearize`, `color_statistic`,
+/// `stop_selection` and `rounding` apply. `image` is BGR with levels in
+/// `[0, 255]`, as 8-bit or 32-bit float.
+pub fn sample_band_color_with_config(
+	image: &Mat,
+	t: &Mat,
+	low: f64,
+	high: f64,
+	config: &GradientConfig,
+) -> Result<[u8; 3]> {
+	let lab = match config.average_space {
+		ColorAverageSpace::Bgr => None,
+		ColorAverageSpace::Lab => Some(to_lab(image)?),
+	};
+	let linear = match (&lab, config.linearize) {
+		(None, true) => Some(to_linear(image)?),
+		_ => None,
+	};
+	let color = sampled_band_color(
+		image,
+		lab.as_ref(),
+		linear.as_ref(),
+		&band_mask(t, low, high)?,
+		config.color_statistic,
+		config.stop_selection,
+		config.rounding,
+	)?;
+	Ok(color.map_or([0, 0, 0], |color| bgr_to_rgb(color.bgr)))
+}
+
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+pub(crate) fn extract_gradient(
+	img: &Mat,
+	config: &GradientConfig,
+	scratch: &mut Scratch,
+) -> Result<GradientResult> {
+	let analysis = analyze(img, config, scratch)?;
+	let result = gradient_from_analysis(&analysis, config)?;
+	scratch.blurred = analysis.blurred;
+	Ok(result)
+}
+
+/// CIE76 distance below which the end colors count as indistinguishable.
+const AMBIGUOUS_DELTA_E: f64 = 5.0;
+/// CIELAB chroma below which a color counts as neutral gray.
+const GRAYSCALE_CHROMA: f64 = 5.0;
+
+pub(crate) fn gradient_from_analysis(
+	analysis: &Analysis,
+	config: &GradientConfig,
+) -> Result<GradientResult> {
+	let direction = &analysis.direction;
+
+	let raw_angle = compass_angle(direction.angle);
+	let axis_angle = axis_snapped_angle(direction);
+	let angle = match config.snap_angle {
+		Some(tolerance) => snap_angle(axis_angle, config.snap_step, tolerance),
+		None => axis_angle,
+	};
+
+	let (min_val, max_val) = (analysis.t_min, analysis.t_max);
+	let threshold_low = min_val + config.band_frac * (max_val - min_val);
+	let threshold_high = max_val - config.band_frac * (max_val - min_val);
+
+	let start_mask = analysis.band_mask(f64::NEG_INFINITY, threshold_low)?;
+	let end_mask = analysis.band_mask(threshold_high, f64::INFINITY)?;
+
+	let black = BandColor {
+		bgr: core::Vec3d::all(0.0),
+		bgr16: [0; 3],
+		clamped: false,
+	};
+	let start = analysis.quantized_band_color(&start_mask)?.unwrap_or(black);
+	let end = analysis.quantized_band_color(&end_mask)?.unwrap_or(black);
+	let (start_bgr, end_bgr) = (start.bgr, end.bgr);
+
+	let start_color = Color::new_1(start_bgr[0], start_bgr[1], start_bgr[2])?;
+
+	let end_color = Color::new_1(end_bgr[0], end_bgr[1], end_bgr[2])?;
+
+	let start_rgb = bgr_to_rgb(start_bgr);
+	let end_rgb = bgr_to_rgb(end_bgr);
+
+	let mid_mask = analysis.band_mask(threshold_low, threshold_high)?;
+	let mid_rgb = match analysis.band_color(&mid_mask)? {
+		Some(bgr) => bgr_to_rgb(bgr),
+		None => [0, 1, 2].map(|c| ((start_rgb[c] as u16 + end_rgb[c] as u16 + 1) / 2) as u8),
+	};
+
+	let start_band_px = core::count_non_zero(&start_mask)?;
+	let end_band_px = core::count_non_zero(&end_mask)?;
+
+	let start_point = mask_centroid(&start_mask)?;
+	let end_point = mask_centroid(&end_mask)?;
+
+	let fit_error = if config.compute_fit_error {
+		Some(fit_error(&analysis, start_bgr, end_bgr)?)
+	} else {
+		None
+	};
+
+	let multimodal = is_multimodal(&analysis.field, direction)?;
+	#[cfg(feature = "tracing")]
+	tracing::debug!(
+		raw_angle,
+		angle,
+		confidence = direction.confidence,
+		multimodal
+	);
+
+	let (kind, center) = kind::classify(&analysis.field, direction, config)?;
+	let (radial, conic) = match (kind, center) {
+		(GradientKind::Radial, Some(center)) => {
+			(Some(kind::radial_info(&analysis, center, config)?), None)
+		}
+		(GradientKind::Conic, Some(center)) => {
+			(None, Some(kind::conic_info(&analysis, center, config)?))
+		}
+		_ => (None, None),
+	};
+	// A flat image has no direction to report.
+	let (angle, raw_angle) = match kind {
+		GradientKind::Flat => (0.0, 0.0),
+		_ => (angle, raw_angle),
+	};
+
+	Ok(GradientResult {
+		start_color: start_color,
+		end_color: end_color,
+		start_rgb,
+		end_rgb,
+		mid_rgb,
+		angle: angle,
+		raw_angle,
+		confidence: direction.confidence,
+		start_point,
+		end_point,
+		start_band_px,
+		end_band_px,
+		t_min: min_val,
+		t_max: max_val,
+		kind,
+		radial,
+		conic,
+		fit_error,
+		multimodal,
+		start_rgb16: rgb16(start.bgr16),
+		end_rgb16: rgb16(end.bgr16),
+		clamped: start.clamped || end.clamped,
+		direction_ambiguous: delta_e(start_rgb, end_rgb) < AMBIGUOUS_DELTA_E,
+		per_channel_angles: analysis.channel_angles,
+		palette_limited: analysis.palette_limited,
+		is_grayscale: [start_rgb, mid_rgb, end_rgb]
+			.iter()
+			.all(|&rgb| chroma(rgb) < GRAYSCALE_CHROMA),
+	})
+}
+
+pub(crate) fn analyze(
+	img: &Mat,
+	config: &GradientConfig,
+	scratch: &mut Scratch,
+) -> Result<Analysis> {
+	analyze_masked(img, None, config, scratch)
+}
+
+/// `mask`, the size of `img`, limits both the angle and the colors to its
+/// non-zero pixels.
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+fn analyze_masked(
+	img: &Mat,
+	mask: Option<&Mat>,
+	config: &GradientConfig,
+	scratch: &mut Scratch,
+) -> Result<Analysis> {
+	config.validate()?;
+	if let Some(mask) = mask
+		&& (mask.channels() != 1 || mask.size()? != img.size()?)
+	{
+		return Err(GradientError::InvalidArgument(format!(
+			"Mask must be single-channel and {}x{}, got {} channels at {}x{}",
+			img.cols(),
+			img.rows(),
+			mask.channels(),
+			mask.cols(),
+			mask.rows()
+		)));
+	}
+	let (cropped, cropped_mask);
+	let (img, mask) = match config.roi {
+		Some(roi) => {
+			cropped = crop(img, roi)?;
+			cropped_mask = mask.map(|mask| crop(mask, roi)).transpose()?;
+			(&cropped, cropped_mask.as_ref())
+		}
+		None => (img, mask),
+	};
+	let (mut img, alpha) = to_bgr(img, config.high_bit_depth)?;
+	// Few-color palette images are usually dithered, so they get the dither
+	// check even without `denoise_dither`.
+	let palette_limited = is_palette_limited(&img)?;
+	if (config.denoise_dither || palette_limited)
+		&& let Some(denoised) = denoise_dither(&img)?
+	{
+		img = denoised;
+	}
+	let mut blurred = match &alpha {
+		Some(alpha) => blur_with_alpha(&img, alpha, config, scratch)?,
+		None => blur_image(&img, config, scratch)?,
+	};
+	// The pixels both the angle and the colors are limited to.
+	let border = border_mask(blurred.size()?, config.border_exclude_frac)?;
+	let region = match mask {
+		Some(mask) => Some(intersect(border, resized_mask(mask, blurred.size()?)?)?),
+		None => border,
+	};
+	let alpha = alpha
+		.map(|alpha| opaque_mask(&alpha, blurred.size()?))
+		.transpose()?;
+	let opaque = match (&alpha, &region) {
+		(Some(alpha), region) => Some(intersect(region.clone(), alpha.clone())?),
+		(None, region) => region.clone(),
+	};
+	// Derivatives next to a transparent pixel still see it, so the angle
+	// keeps the kernel radius away from them.
+	let angle_region = match &alpha {
+		Some(alpha) => Some(intersect(
+			region.clone(),
+			erode_mask(alpha, config.edge_operator.kernel_size() / 2)?,
+		)?),
+		None => region.clone(),
+	};
+
+	// The angle comes from the unbalanced grayscale; white balance only
+	// changes the sampled colors.
+	if opencl_enabled(config)? {
+		with_opencl(|| {
+			let src = blurred.get_umat(core::AccessFlag::ACCESS_READ, UMAT_USAGE)?;
+			let mut gray = core::UMat::new(UMAT_USAGE);
+			gray_image(&src, &mut gray)?;
+			gray.copy_to(&mut scratch.gray)?;
+			Ok(())
+		})?;
+	} else {
+		gray_image(&blurred, &mut scratch.gray)?;
+	}
+	if config.normalize_contrast {
+		stretch_contrast(&mut scratch.gray)?;
+	}
+	if config.white_balance {
+		blurred = gray_world(&blurred, opaque.as_ref())?;
+	}
+
+	let lab = match config.average_space {
+		ColorAverageSpace::Bgr => None,
+		ColorAverageSpace::Lab => Some(to_lab(&blurred)?),
+	};
+	let linear = match (&lab, config.linearize) {
+		(None, true) => Some(to_linear(&blurred)?),
+		_ => None,
+	};
+	let start = Instant::now();
+	let field = gradient_field(
+		&scratch.gray,
+		config,
+		angle_region.as_ref(),
+		&mut scratch.grad_x,
+		&mut scratch.grad_y,
+	)?;
+	scratch.timing.sobel = start.elapsed();
+	let start = Instant::now();
+	let mut direction = field_direction(&field)?;
+	if config.orientation == Orientation::TopLeftStart
+		&& direction.angle.cos() + direction.angle.sin() < 0.0
+	{
+		direction.angle += std::f64::consts::PI;
+	}
+	let t = project(&blurred, direction.angle.cos(), direction.angle.sin())?;
+	scratch.timing.angle = start.elapsed();
+
+	let channel_angles = if config.per_channel_angles {
+		Some(estimate_channel_angles(
+			&blurred,
+			config,
+			angle_region.as_ref(),
+		)?)
+	} else {
+		None
+	};
+
+	// The range spans only the analysed pixels, so the end bands fall on the
+	// ends of the region rather than in an excluded margin or transparent
+	// frame. With nothing analysed it falls back to the whole image.
+	let range_mask = match &opaque {
+		Some(opaque) if core::count_non_zero(opaque)? > 0 => Some(opaque),
+		_ => None,
+	};
+	let mut t_min = 0.0;
+	let mut t_max = 0.0;
+	match range_mask {
+		Some(mask) => core::min_max_loc(&t, Some(&mut t_min), Some(&mut t_max), None, None, mask)?,
+		None => core::min_max_loc(
+			&t,
+			Some(&mut t_min),
+			Some(&mut t_max),
+			None,
+			None,
+			&core::no_array(),
+		)?,
+	}
+
+	Ok(Analysis {
+		blurred,
+		lab,
+		linear,
+		opaque,
+		field,
+		direction,
+		t,
+		t_min,
+		t_max,
+		statistic: config.color_statistic,
+		rounding: config.rounding,
+		selection: config.stop_selection,
+		channel_angles,
+		palette_limited,
+	})
+}
+
+/// Compass angle of every channel of `blurred`, in RGB order.
+fn estimate_channel_angles(
+	blurred: &Mat,
+	config: &GradientConfig,
+	region: Option<&Mat>,
+) -> Result<[f64; 3]> {
+	let (mut grad_x, mut grad_y) = (Mat::default(), Mat::default());
+	let mut angles = [0.0; 3];
+	for (c, angle) in angles.iter_mut().rev().enumerate() {
+		let mut channel = Mat::default();
+		core::extract_channel(blurred, &mut channel, c as i32)?;
+		let field = gradient_field(&channel, config, region, &mut grad_x, &mut grad_y)?;
+		*angle = compass_angle(field_direction(&field)?.angle);
+	}
+	Ok(angles)
+}
+
+/// Compares the blurred image with a linear ramp from `start` to `end` over
+/// the projection range, ignoring transparent pixels.
+fn fit_error(analysis: &Analysis, start: core::Vec3d, end: core::Vec3d) -> Result<f64> {
+	let residual = fit_residual(analysis, start, end)?;
+	match &analysis.opaque {
+		Some(opaque) if core::count_non_zero(opaque)? == 0 => Ok(0.0),
+		Some(opaque) => Ok(core::mean(&residual, opaque)?[0]),
+		None => Ok(core::mean(&residual, &core::no_array())?[0]),
+	}
+}
+
+/// Per-pixel mean absolute difference between the blurred image and the
+/// linear ramp from `start` to `end`, as CV_64F. Transparent pixels are 0.
+pub(crate) fn fit_residual(
+	analysis: &Analysis,
+	start: core::Vec3d,
+	end: core::Vec3d,
+) -> Result<Mat> {
+	let span = (analysis.t_max - analysis.t_min).max(f64::EPSILON);
+	let mut blurred = Mat::default();
+	analysis
+		.blurred
+		.convert_to(&mut blurred, CV_64F, 1.0, 0.0)?;
+
+	let mut residual =
+		Mat::new_rows_cols_with_default(blurred.rows(), blurred.cols(), CV_64F, Scalar::all(0.0))?;
+	for y in 0..blurred.rows() {
+		let pixels = blurred.at_row::<core::Vec3d>(y)?;
+		let t_row = analysis.t.at_row::<f32>(y)?;
+		let opaque_row = match &analysis.opaque {
+			Some(opaque) => Some(opaque.at_row::<u8>(y)?),
+			None => None,
+		};
+
+		let row = residual.at_row_mut::<f64>(y)?;
+		for (x, (pixel, &t)) in pixels.iter().zip(t_row).enumerate() {
+			if opaque_row.is_some_and(|row| row[x] == 0) {
+				continue;
+			}
+			let f = (t as f64 - analysis.t_min) / span;
+			let mut total = 0.0;
+			for ((&s, &e), &p) in start.0.iter().zip(&end.0).zip(&pixel.0) {
+				total += (p - (s + (e - s) * f)).abs();
+			}
+			row[x] = total / 3.0;
+		}
+	}
+
+	Ok(residual)
+}
+
+pub(crate) fn bgr_to_rgb(bgr: core::Vec3d) -> [u8; 3] {
+	[bgr[2] as u8, bgr[1] as u8, bgr[0] as u8]
+}
+
+fn rgb16(bgr: [u16; 3]) -> [u16; 3] {
+	[bgr[2], bgr[1], bgr[0]]
+}
+
+fn mask_centroid(mask: &Mat) -> Result<Option<(i32, i32)>> {
+	let moments = imgproc::moments(mask, true)?;
+	if moments.m00 == 0.0 {
+		return Ok(None);
+	}
+
+	Ok(Some((
+		(moments.m10 / moments.m00).round() as i32,
+		(moments.m01 / moments.m00).round() as i32,
+	)))
+}
+
+pub(crate) fn read_file(image_path: &Path) -> Result<Vec<u8>> {
+	std::fs::read(image_path).map_err(|source| GradientError::ReadFailed {
+		path: image_path.to_path_buf(),
+		source,
+	})
+}
+
+pub(crate) fn read_image(image_path: &Path, config: &GradientConfig) -> Result<Mat> {
+	read_image_with(image_path, config, &mut Vec::new())
+}
+
+/// Like `read_image`, reading the file into `buffer`, whose allocation is
+/// kept between calls. With `config.memory_map` the file is mapped instead
+/// and `buffer` is left alone.
+pub(crate) fn read_image_with(
+	image_path: &Path,
+	config: &GradientConfig,
+	buffer: &mut Vec<u8>,
+) -> Result<Mat> {
+	config.validate()?;
+	#[cfg(feature = "memmap")]
+	if config.memory_map
+		&& let Some(map) = map_file(image_path)
+	{
+		return decode_image(&map, config);
+	}
+
+	buffer.clear();
+	std::fs::File::open(image_path)
+		.and_then(|mut file| std::io::Read::read_to_end(&mut file, buffer))
+		.map_err(|source| GradientError::ReadFailed {
+			path: image_path.to_path_buf(),
+			source,
+		})?;
+	decode_image(buffer, config)
+}
+
+/// `None` when the file cannot be opened or mapped, so the caller falls back
+/// to reading it and reports the error from there.
+#[cfg(feature = "memmap")]
+fn map_file(image_path: &Path) -> Option<memmap2::Mmap> {
+	let file = std::fs::File::open(image_path).ok()?;
+	// SAFETY: the map is only read while decoding, and the decoded Mat owns
+	// its pixels, so nothing borrows the map past this call's caller. This
+	// still relies on no other process truncating the file meanwhile, as
+	// documented on `GradientConfig::memory_map`.
+	unsafe { memmap2::Mmap::map(&file) }.ok()
+}
+
+pub(crate) fn decode_image(data: &[u8], config: &GradientConfig) -> Result<Mat> {
+	if data.is_empty() {
+		return Err(GradientError::EmptyImage);
+	}
+
+	let img = decode_stored(data)?;
+	if config.respect_exif {
+		return apply_orientation(img, exif_orientation(data));
+	}
+	Ok(img)
+}
+
+/// The first frame as stored, before any EXIF orientation.
+fn decode_stored(data: &[u8]) -> Result<Mat> {
+	if opencv_decodes(data)? {
+		let buf = Mat::from_slice(data)?;
+		let img = imgcodecs::imdecode(&buf, imgcodecs::IMREAD_UNCHANGED)?;
+		if !img.empty() {
+			return Ok(img);
+		}
+	}
+	decode_unsupported(data)
+}
+
+fn decode_frames(data: &[u8], config: &GradientConfig) -> Result<Vec<Mat>> {
+	if data.is_empty() {
+		return Err(GradientError::EmptyImage);
+	}
+
+	let mut decoded = core::Vector::<Mat>::new();
+	let frames = if opencv_decodes(data)?
+		&& imgcodecs::imdecodemulti_def(
+			&Mat::from_slice(data)?,
+			imgcodecs::IMREAD_UNCHANGED,
+			&mut decoded,
+		)? && !decoded.is_empty()
+	{
+		decoded.into_iter().collect()
+	} else {
+		decode_unsupported_frames(data)?
+	};
+
+	let orientation = if config.respect_exif {
+		exif_orientation(data)
+	} else {
+		1
+	};
+	frames
+		.into_iter()
+		.map(|frame| apply_orientation(frame, orientation))
+		.collect()
+}
+
+/// Called when the linked OpenCV could not decode `data`. WebP, AVIF, HEIF
+/// and JPEG XL are only decoded by OpenCV builds with the matching
+/// `imgcodecs` backends (`WITH_WEBP`, `WITH_AVIF`, ...). With the `image`
+/// feature, formats the `image` crate can read are decoded by it instead.
+/// Images with an alpha channel come back as BGRA, like OpenCV decodes them.
+fn decode_unsupported(data: &[u8]) -> Result<Mat> {
+	#[cfg(feature = "image")]
+	if let Ok(img) = image::load_from_memory(data) {
+		if img.color().has_alpha() {
+			return rgba_to_bgra(&img.to_rgba8());
+		}
+		return rgb_to_bgr(&img.to_rgb8());
+	}
+
+	Err(GradientError::UnsupportedFormat(match sniff_format(data) {
+		Some(format) => format!("{} is not supported by the linked OpenCV build", format),
+		None => "Image could not be decoded".to_string(),
+	}))
+}
+
+/// Called when the linked OpenCV could not split `data` into frames.
+/// Animated GIF and WebP are read by the `image` crate; without the `image`
+/// feature they fail rather than coming back as a single frame. Anything
+/// else is decoded as one frame.
+fn decode_unsupported_frames(data: &[u8]) -> Result<Vec<Mat>> {
+	#[cfg(feature = "image")]
+	if let Some(frames) = decode_animation(data)? {
+		return Ok(frames);
+	}
+	#[cfg(not(feature = "image"))]
+	if let Some(format @ ("GIF" | "WebP")) = sniff_format(data) {
+		return Err(GradientError::UnsupportedFormat(format!(
+			"{} frames need the `image` feature with the linked OpenCV build",
+			format
+		)));
+	}
+
+	Ok(vec![decode_stored(data)?])
+}
+
+/// The frames of an animated GIF or WebP as BGRA Mats, or `None` for other
+/// data and still WebP images.
+#[cfg(feature = "image")]
+fn decode_animation(data: &[u8]) -> Result<Option<Vec<Mat>>> {
+	use image::AnimationDecoder;
+	use image::codecs::{gif::GifDecoder, webp::WebPDecoder};
+
+	let cursor = std::io::Cursor::new(data);
+	let frames = match sniff_format(data) {
+		Some("GIF") => GifDecoder::new(cursor)?.into_frames(),
+		Some("WebP") => {
+			let decoder = WebPDecoder::new(cursor)?;
+			if !decoder.has_animation() {
+				return Ok(None);
+			}
+			decoder.into_frames()
+		}
+		_ => return Ok(None),
+	};
+
+	frames
+		.map(|frame| rgba_to_bgra(&frame?.into_buffer()))
+		.collect::<Result<Vec<_>>>()
+		.map(Some)
+}
+
+/// Whether the linked OpenCV has a codec for `data`, checked before decoding
+/// so that a missing one is reported as such. Only the formats
+/// `sniff_format` names can be missing. OpenCV can only look for a reader
+/// in a file, so the check asks for the writer, which the same build option
+/// adds alongside it.
+fn opencv_decodes(data: &[u8]) -> Result<bool> {
+	let extension = match sniff_format(data) {
+		None => return Ok(true),
+		Some("WebP") => ".webp",
+		Some("AVIF") => ".avif",
+		Some("HEIF") => ".heic",
+		Some("JPEG XL") => ".jxl",
+		Some("GIF") => ".gif",
+		Some(_) => return Ok(true),
+	};
+	Ok(imgcodecs::have_image_writer(extension)?)
+}
+
+/// Names formats OpenCV may be built without, from their magic bytes.
+fn sniff_format(data: &[u8]) -> Option<&'static str> {
+	let brand = data.get(4..12);
+	if data.starts_with(b"RIFF") && matches!(data.get(8..12), Some(b"WEBP")) {
+		Some("WebP")
+	} else if matches!(brand, Some(b"ftypavif" | b"ftypavis")) {
+		Some("AVIF")
+	} else if matches!(brand, Some(b"ftypheic" | b"ftypheix" | b"ftypmif1")) {
+		Some("HEIF")
+	} else if data.starts_with(&[0xFF, 0x0A]) || data.starts_with(b"\0\0\0\x0cJXL ") {
+		Some("JPEG XL")
+	} else if data.starts_with(b"GIF8") {
+		Some("GIF")
+	} else {
+		None
+	}
+}
+
+/// `IMREAD_UNCHANGED` leaves the pixels as stored, so the EXIF orientation
+/// is read separately. Images without EXIF data count as upright.
+fn exif_orientation(data: &[u8]) -> u32 {
+	exif::Reader::new()
+		.read_from_container(&mut std::io::Cursor::new(data))
+		.ok()
+		.and_then(|exif| {
+			exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?
+				.value
+				.get_uint(0)
+		})
+		.unwrap_or(1)
+}
+
+fn apply_orientation(img: Mat, orientation: u32) -> Result<Mat> {
+	let (rotation, flip) = match orientation {
+		2 => (None, Some(1)),
+		3 => (Some(core::ROTATE_180), None),
+		4 => (None, Some(0)),
+		5 => (Some(core::ROTATE_90_CLOCKWISE), Some(1)),
+		6 => (Some(core::ROTATE_90_CLOCKWISE), None),
+		7 => (Some(core::ROTATE_90_COUNTERCLOCKWISE), Some(1)),
+		8 => (Some(core::ROTATE_90_COUNTERCLOCKWISE), None),
+		_ => (None, None),
+	};
+
+	let mut img = img;
+	if let Some(rotation) = rotation {
+		let mut rotated = Mat::default();
+		core::rotate(&img, &mut rotated, rotation)?;
+		img = rotated;
+	}
+	if let Some(flip_code) = flip {
+		let mut flipped = Mat::default();
+		core::flip(&img, &mut flipped, flip_code)?;
+		img = flipped;
+	}
+	Ok(img)
+}
+
+/// Copies `roi` out of `img` into a continuous Mat of its own. A
+/// `Mat::roi` view cannot be passed on as a `&Mat`, and the copy is small
+/// next to the resize that follows.
+fn crop(img: &Mat, roi: core::Rect) -> Result<Mat> {
+	let bounds = core::Rect::new(0, 0, img.cols(), img.rows());
+	if roi.width <= 0 || roi.height <= 0 || (roi & bounds) != roi {
+		return Err(GradientError::InvalidArgument(format!(
+			"ROI {:?} is empty or outside the {}x{} image",
+			roi,
+			img.cols(),
+			img.rows()
+		)));
+	}
+
+	Ok(img.roi(roi)?.clone_pointee())
+}
+
+/// Converts to 3-channel BGR with levels in `[0, 255]`, as 8-bit or, with
+/// `high_bit_depth`, as 32-bit float so 16-bit and float inputs keep their
+/// precision. The alpha channel, if any, is returned as an 8-bit Mat.
+fn to_bgr(img: &Mat, high_bit_depth: bool) -> Result<(Mat, Option<Mat>)> {
+	let scale = match img.depth() {
+		core::CV_8U => 1.0,
+		core::CV_16U => 1.0 / 257.0,
+		core::CV_32F | CV_64F => 255.0,
+		depth => {
+			return Err(GradientError::UnsupportedFormat(format!(
+				"Unsupported image depth {}",
+				depth
+			)));
+		}
+	};
+
+	let depth = if high_bit_depth { CV_32F } else { core::CV_8U };
+	let mut converted = Mat::default();
+	img.convert_to(&mut converted, depth, scale, 0.0)?;
+
+	let (code, alpha) = match converted.channels() {
+		1 => (imgproc::COLOR_GRAY2BGR, None),
+		3 => return Ok((converted, None)),
+		4 => {
+			let mut alpha = Mat::default();
+			core::extract_channel(&converted, &mut alpha, 3)?;
+			let mut alpha8 = Mat::default();
+			alpha.convert_to(&mut alpha8, core::CV_8U, 1.0, 0.0)?;
+			(imgproc::COLOR_BGRA2BGR, Some(alpha8))
+		}
+		channels => {
+			return Err(GradientError::UnsupportedFormat(format!(
+				"Unsupported channel count {}",
+				channels
+			)));
+		}
+	};
+
+	let mut bgr = Mat::default();
+	imgproc::cvt_color(
+		&converted,
+		&mut bgr,
+		code,
+		0,
+		core::AlgorithmHint::ALGO_HINT_DEFAULT,
+	)?;
+
+	Ok((bgr, alpha))
+}
+
+/// Stretches the gray levels to the full `[0, 255]` range, in float so a
+/// faint ramp over a handful of levels is not re-quantized.
+fn stretch_contrast(gray: &mut Mat) -> Result<()> {
+	let mut gray_f32 = Mat::default();
+	gray.convert_to(&mut gray_f32, CV_32F, 1.0, 0.0)?;
+	core::normalize(
+		&gray_f32,
+		gray,
+		0.0,
+		255.0,
+		core::NORM_MINMAX,
+		CV_32F,
+		&core::no_array(),
+	)?;
+	Ok(())
+}
+
+/// At most this many distinct colors mark an image as palette-limited.
+pub const PALETTE_MAX_COLORS: usize = 8;
+
+/// Fewer distinct colors are a flat fill or a hard edge, not a quantized
+/// gradient.
+const PALETTE_MIN_COLORS: usize = 3;
+
+/// Whether an 8-bit BGR image uses from `PALETTE_MIN_COLORS` to
+/// `PALETTE_MAX_COLORS` colors. Stops at the first color past the limit, so
+/// ordinary images return after a few pixels. Float images are never
+/// palette-limited.
+fn is_palette_limited(img: &Mat) -> Result<bool> {
+	if img.depth() != core::CV_8U {
+		return Ok(false);
+	}
+
+	let mut colors: Vec<core::Vec3b> = Vec::with_capacity(PALETTE_MAX_COLORS + 1);
+	for y in 0..img.rows() {
+		for pixel in img.at_row::<core::Vec3b>(y)? {
+			if !colors.contains(pixel) {
+				if colors.len() == PALETTE_MAX_COLORS {
+					return Ok(false);
+				}
+				colors.push(*pixel);
+			}
+		}
+	}
+	Ok(colors.len() >= PALETTE_MIN_COLORS)
+}
+
+/// Median filter size that erases the two-color patterns of dithering.
+const DITHER_KSIZE: i32 = 5;
+/// Mean per-channel change, in 8-bit levels, the median filter has to make
+/// for the image to count as dithered.
+const DITHER_MIN_NOISE: f64 = 8.0;
+/// Share of the filtered image's standard deviation the change has to
+/// exceed, so strong but locally smooth content is not mistaken for dither.
+const DITHER_NOISE_RATIO: f64 = 0.25;
+
+/// Median-filters the full-size image when it looks dithered: lots of
+/// pixel-level noise that the filter removes, compared to the variation
+/// that is left. Returns `None` for other images.
+fn denoise_dither(img: &Mat) -> Result<Option<Mat>> {
+	let mut filtered = Mat::default();
+	imgproc::median_blur(img, &mut filtered, DITHER_KSIZE)?;
+
+	let mut diff = Mat::default();
+	core::absdiff(img, &filtered, &mut diff)?;
+	let change = core::mean(&diff, &core::no_array())?;
+	let noise = (change[0] + change[1] + change[2]) / 3.0;
+
+	let mut mean = Mat::default();
+	let mut std_dev = Mat::default();
+	core::mean_std_dev(&filtered, &mut mean, &mut std_dev, &core::no_array())?;
+	let spread = std_dev.data_typed::<f64>()?.iter().sum::<f64>() / 3.0;
+
+	if noise >= DITHER_MIN_NOISE && noise > DITHER_NOISE_RATIO * spread {
+		Ok(Some(filtered))
+	} else {
+		Ok(None)
+	}
+}
+
+/// Gray-world white balance: scales each channel so its mean over the
+/// opaque pixels matches the mean of all three.
+fn gray_world(image: &Mat, opaque: Option<&Mat>) -> Result<Mat> {
+	let means = match opaque {
+		Some(opaque) => core::mean(image, opaque)?,
+		None => core::mean(image, &core::no_array())?,
+	};
+	let gray = (means[0] + means[1] + means[2]) / 3.0;
+	let gain = |mean: f64| if mean > 0.0 { gray / mean } else { 1.0 };
+
+	let mut balanced = Mat::default();
+	core::multiply(
+		image,
+		&Scalar::new(gain(means[0]), gain(means[1]), gain(means[2]), 1.0),
+		&mut balanced,
+		1.0,
+		-1,
+	)?;
+	Ok(balanced)
+}
+
+/// Shrinks the non-zero pixels of `mask` by `radius`. Pixels outside the
+/// image count as set, so only the edges of the mask itself move.
+fn erode_mask(mask: &Mat, radius: i32) -> Result<Mat> {
+	let size = core::Size::new(2 * radius + 1, 2 * radius + 1);
+	let kernel = imgproc::get_structuring_element_def(imgproc::MORPH_RECT, size)?;
+	let mut eroded = Mat::default();
+	imgproc::erode_def(mask, &mut eroded, &kernel)?;
+	Ok(eroded)
+}
+
+fn opaque_mask(alpha: &Mat, size: core::Size) -> Result<Mat> {
+	let mut small = Mat::default();
+	imgproc::resize(alpha, &mut small, size, 0.0, 0.0, imgproc::INTER_AREA)?;
+
+	let mut mask = Mat::default();
+	imgproc::threshold(&small, &mut mask, 0.0, 255.0, imgproc::THRESH_BINARY)?;
+	Ok(mask)
+}
+
+/// `mask` shrunk to `size`, keeping the pixels that are at least half
+/// covered.
+fn resized_mask(mask: &Mat, size: core::Size) -> Result<Mat> {
+	let mut mask8 = Mat::default();
+	mask.convert_to(&mut mask8, core::CV_8U, 1.0, 0.0)?;
+	let mut binary = Mat::default();
+	imgproc::threshold(&mask8, &mut binary, 0.0, 255.0, imgproc::THRESH_BINARY)?;
+
+	let mut small = Mat::default();
+	imgproc::resize(&binary, &mut small, size, 0.0, 0.0, imgproc::INTER_AREA)?;
+	let mut resized = Mat::default();
+	imgproc::threshold(&small, &mut resized, 127.0, 255.0, imgproc::THRESH_BINARY)?;
+	Ok(resized)
+}
+
+fn intersect(a: Option<Mat>, b: Mat) -> Result<Mat> {
+	let Some(a) = a else {
+		return Ok(b);
+	};
+	let mut both = Mat::default();
+	core::bitwise_and(&a, &b, &mut both, &core::no_array())?;
+	Ok(both)
+}
+
+/// Mask of the pixels at least `frac` of the width and height away from the
+/// borders, or `None` when nothing is excluded.
+fn border_mask(size: core::Size, frac: f64) -> Result<Option<Mat>> {
+	if frac <= 0.0 {
+		return Ok(None);
+	}
+	let margin_x = (size.width as f64 * frac) as i32;
+	let margin_y = (size.height as f64 * frac) as i32;
+	let mut mask = Mat::new_size_with_default(size, core::CV_8UC1, Scalar::all(0.0))?;
+	imgproc::rectangle(
+		&mut mask,
+		core::Rect::new(
+			margin_x,
+			margin_y,
+			size.width - 2 * margin_x,
+			size.height - 2 * margin_y,
+		),
+		Scalar::all(255.0),
+		imgproc::FILLED,
+		imgproc::LINE_8,
+		0,
+	)?;
+	Ok(Some(mask))
+}
+
+fn blur_image(img: &Mat, config: &GradientConfig, scratch: &mut Scratch) -> Result<Mat> {
+	let size = img.size()?;
+	if size.width <= 0 || size.height <= 0 {
+		return Err(GradientError::EmptyImage);
+	}
+	let (width, height) = config
+		.resize_strategy
+		.target_size(size.width as f64, size.height as f64);
+	let target = core::Size::new(width, height);
+	let enlarging = target.width > size.width || target.height > size.height;
+	let interpolation = if enlarging && config.interpolation == imgproc::INTER_AREA {
+		imgproc::INTER_LINEAR
+	} else {
+		config.interpolation
+	};
+	#[cfg(feature = "tracing")]
+	tracing::debug!(
+		input_width = size.width,
+		input_height = size.height,
+		width = target.width,
+		height = target.height,
+		interpolation,
+		"resizing"
+	);
+
+	let mut blurred = std::mem::take(&mut scratch.blurred);
+	if opencl_enabled(config)? {
+		with_opencl(|| {
+			let src = img.get_umat(core::AccessFlag::ACCESS_READ, UMAT_USAGE)?;
+			let mut small = core::UMat::new(UMAT_USAGE);
+			let mut blurred_gpu = core::UMat::new(UMAT_USAGE);
+			resize_and_blur(
+				&src,
+				&mut small,
+				&mut blurred_gpu,
+				target,
+				interpolation,
+				config.blur_sigma,
+				&mut scratch.timing,
+			)?;
+			blurred_gpu.copy_to(&mut blurred)?;
+			small.copy_to(&mut scratch.small)?;
+			Ok(())
+		})?;
+	} else {
+		resize_and_blur(
+			img,
+			&mut scratch.small,
+			&mut blurred,
+			target,
+			interpolation,
+			config.blur_sigma,
+			&mut scratch.timing,
+		)?;
+	}
+
+	Ok(blurred)
+}
+
+pub(crate) const UMAT_USAGE: core::UMatUsageFlags = core::UMatUsageFlags::USAGE_DEFAULT;
+
+/// Whether `config` asks for OpenCL and the linked OpenCV can provide it.
+pub(crate) fn opencl_enabled(config: &GradientConfig) -> Result<bool> {
+	Ok(config.use_opencl && core::have_opencl()?)
+}
+
+/// Runs `f` with OpenCV's OpenCL switch turned on, so the `UMat` calls in it
+/// run on the device, and restores the previous setting afterwards. OpenCV
+/// keeps the switch per thread, so other threads are not affected.
+pub(crate) fn with_opencl<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+	let previous = core::use_opencl()?;
+	core::set_use_opencl(true)?;
+	let result = f();
+	core::set_use_opencl(previous)?;
+	result
+}
+
+/// Blurs `img` premultiplied by `alpha` and divides by the blurred alpha
+/// afterwards, so whatever color transparent pixels store does not bleed
+/// into their opaque neighbours. Fully transparent pixels come out black.
+/// `scratch.small` is un-premultiplied the same way.
+fn blur_with_alpha(
+	img: &Mat,
+	alpha: &Mat,
+	config: &GradientConfig,
+	scratch: &mut Scratch,
+) -> Result<Mat> {
+	let mut weight = Mat::default();
+	alpha.convert_to(&mut weight, CV_32F, 1.0 / 255.0, 0.0)?;
+	let mut weights = Mat::default();
+	imgproc::cvt_color(
+		&weight,
+		&mut weights,
+		imgproc::COLOR_GRAY2BGR,
+		0,
+		core::AlgorithmHint::ALGO_HINT_DEFAULT,
+	)?;
+	let mut levels = Mat::default();
+	img.convert_to(&mut levels, CV_32F, 1.0, 0.0)?;
+	let mut premultiplied = Mat::default();
+	core::multiply(&levels, &weights, &mut premultiplied, 1.0, -1)?;
+
+	let blurred = blur_image(&premultiplied, config, scratch)?;
+	let mut weight_scratch = Scratch::default();
+	let blurred_weights = blur_image(&weights, config, &mut weight_scratch)?;
+	scratch.small = unpremultiply(&scratch.small, &weight_scratch.small, img.depth())?;
+	unpremultiply(&blurred, &blurred_weights, img.depth())
+}
+
+fn unpremultiply(image: &Mat, weights: &Mat, depth: i32) -> Result<Mat> {
+	let mut divided = Mat::default();
+	core::divide2(image, weights, &mut divided, 1.0, -1)?;
+	// Where every weight was zero, 0 / 0 left a NaN.
+	core::patch_na_ns(&mut divided, 0.0)?;
+	let mut converted = Mat::default();
+	divided.convert_to(&mut converted, depth, 1.0, 0.0)?;
+	Ok(converted)
+}
+
+/// Generic over `Mat` and `UMat`; with `UMat`s OpenCV runs both steps
+/// through OpenCL.
+fn resize_and_blur<T: core::ToInputArray + core::ToOutputArray>(
+	img: &impl core::ToInputArray,
+	small: &mut T,
+	blurred: &mut impl core::ToOutputArray,
+	target: core::Size,
+	interpolation: i32,
+	sigma: f64,
+	timing: &mut Timing,
+) -> Result<()> {
+	let start = Instant::now();
+	imgproc::resize(img, small, target, 0.0, 0.0, interpolation)?;
+	timing.resize = start.elapsed();
+	let start = Instant::now();
+	imgproc::gaussian_blur(
+		&*small,
+		blurred,
+		core::Size::new(0, 0),
+		sigma,
+		sigma,
+		BorderTypes::BORDER_REFLECT_101 as i32,
+		core::AlgorithmHint::ALGO_HINT_ACCURATE,
+	)?;
+	timing.blur = start.elapsed();
+	Ok(())
+}
+
+fn project(blurred: &Mat, dx: f64, dy: f64) -> Result<Mat> {
+	let h = blurred.rows();
+	let w = blurred.cols();
+
+	let mut t = Mat::new_rows_cols_with_default(h, w, CV_32F, Scalar::all(0.0))?;
+	let fill_row = |y: usize, row: &mut [f32]| {
+		for (x, value) in row.iter_mut().enumerate() {
+			*value = (x as f32) * dx as f32 + (y as f32) * dy as f32;
+		}
+	};
+
+	#[cfg(feature = "rayon")]
+	if h as usize * w as usize >= crate::PARALLEL_MIN_PIXELS {
+		use rayon::prelude::*;
+
+		// `t` was just allocated, so it is continuous and splits into rows.
+		t.data_typed_mut::<f32>()?
+			.par_chunks_mut(w as usize)
+			.enumerate()
+			.for_each(|(y, row)| fill_row(y, row));
+		return Ok(t);
+	}
+
+	for y in 0..h {
+		fill_row(y as usize, t.at_row_mut::<f32>(y)?);
+	}
+
+	Ok(t)
+}
+
+pub(crate) fn band_mask(t: &Mat, low: f64, high: f64) -> Result<Mat> {
+	let mut mask = Mat::default();
+	core::in_range(t, &Scalar::all(low), &Scalar::all(high), &mut mask)?;
+	Ok(mask)
+}
+
+/// Clamps each channel to `[0, 255]` and rounds it to whole 8-bit and
+/// 16-bit levels.
+fn quantize(value: [f64; 3], rounding: RoundingMode) -> BandColor {
+	let clamped = value.iter().any(|c| !(0.0..=255.0).contains(c));
+	let value = value.map(|c| c.clamp(0.0, 255.0));
+	BandColor {
+		bgr: core::Vec3d::from(value.map(|c| rounding.apply(c))),
+		bgr16: value.map(|c| rounding.apply(c * 257.0) as u16),
+		clamped,
+	}
+}
+
+fn band_statistic(image: &Mat, mask: &Mat, statistic: ColorStatistic) -> Result<[f64; 3]> {
+	match statistic {
+		ColorStatistic::Mean => {
+			let mean_val = core::mean(image, mask)?;
+			Ok([mean_val[0], mean_val[1], mean_val[2]])
+		}
+		ColorStatistic::Median => Ok(masked_pixels(image, mask)?.map(|mut values| {
+			if values.is_empty() {
+				return 0.0;
+			}
+			values.sort_unstable_by(f64::total_cmp);
+			values[values.len() / 2]
+		})),
+		ColorStatistic::TrimmedMean { trim_frac } => {
+			Ok(masked_pixels(image, mask)?.map(|mut values| {
+				values.sort_unstable_by(f64::total_cmp);
+				let trim = (values.len() as f64 * trim_frac) as usize;
+				let kept = &values[trim..values.len() - trim];
+				if kept.is_empty() {
+					return 0.0;
+				}
+				kept.iter().sum::<f64>() / kept.len() as f64
+			}))
+		}
+	}
+}
+
+/// Center of the most populous of `k` k-means clusters of the masked pixels.
+fn dominant_cluster(image: &Mat, mask: &Mat, k: usize) -> Result<[f64; 3]> {
+	Ok(clusters(image, mask, k)?
+		.iter()
+		.max_by_key(|&&(_, population)| population)
+		.map_or([0.0; 3], |&(center, _)| center))
+}
+
+/// Centers and pixel counts of `k` k-means clusters of the masked pixels,
+/// fewer when there are fewer pixels.
+pub(crate) fn clusters(image: &Mat, mask: &Mat, k: usize) -> Result<Vec<([f64; 3], usize)>> {
+	let [c0, c1, c2] = masked_pixels(image, mask)?;
+	let n = c0.len();
+	let k = k.min(n);
+	if k == 0 {
+		return Ok(Vec::new());
+	}
+
+	let mut samples = Mat::new_rows_cols_with_default(n as i32, 3, CV_32F, Scalar::all(0.0))?;
+	for (i, ((&a, &b), &c)) in c0.iter().zip(&c1).zip(&c2).enumerate() {
+		samples
+			.at_row_mut::<f32>(i as i32)?
+			.copy_from_slice(&[a as f32, b as f32, c as f32]);
+	}
+
+	// Random seeding would draw from OpenCV's thread-local RNG, which the
+	// caller may be using too. Starting from the pixels split into `k`
+	// brightness ranks in a single attempt draws nothing from it and gives
+	// the same clusters every time.
+	let mut order: Vec<usize> = (0..n).collect();
+	order.sort_by(|&i, &j| (c0[i] + c1[i] + c2[i]).total_cmp(&(c0[j] + c1[j] + c2[j])));
+	let mut labels = Mat::new_rows_cols_with_default(n as i32, 1, core::CV_32S, Scalar::all(0.0))?;
+	for (rank, &i) in order.iter().enumerate() {
+		*labels.at_2d_mut::<i32>(i as i32, 0)? = (rank * k / n) as i32;
+	}
+	let mut centers = Mat::default();
+	core::kmeans(
+		&samples,
+		k as i32,
+		&mut labels,
+		core::TermCriteria::new(core::TermCriteria_COUNT + core::TermCriteria_EPS, 20, 0.5)?,
+		1,
+		core::KMEANS_USE_INITIAL_LABELS,
+		&mut centers,
+	)?;
+
+	let mut population = vec![0usize; k];
+	for i in 0..labels.rows() {
+		population[*labels.at_2d::<i32>(i, 0)? as usize] += 1;
+	}
+	(0..k)
+		.map(|i| {
+			let center = centers.at_row::<f32>(i as i32)?;
+			Ok((
+				[center[0] as f64, center[1] as f64, center[2] as f64],
+				population[i],
+			))
+		})
+		.collect()
+}
+
+/// Collects the per-channel values of the masked pixels of an 8-bit BGR or
+/// 32-bit float Lab image.
+fn masked_pixels(image: &Mat, mask: &Mat) -> Result<[Vec<f64>; 3]> {
+	let mut channels: [Vec<f64>; 3] = Default::default();
+	for y in 0..image.rows() {
+		let mask_row = mask.at_row::<u8>(y)?;
+		if image.depth() == CV_32F {
+			push_masked(&mut channels, mask_row, image.at_row::<core::Vec3f>(y)?);
+		} else {
+			push_masked(&mut channels, mask_row, image.at_row::<core::Vec3b>(y)?);
+		}
+	}
+	Ok(channels)
+}
+
+fn push_masked<T: Copy + Into<f64>>(
+	channels: &mut [Vec<f64>; 3],
+	mask_row: &[u8],
+	row: &[core::VecN<T, 3>],
+) {
+	for (_, pixel) in mask_row.iter().zip(row).filter(|&(&m, _)| m != 0) {
+		for (values, &c) in channels.iter_mut().zip(pixel.0.iter()) {
+			values.push(c.into());
+		}
+	}
+}
+
+fn to_lab(image: &Mat) -> Result<Mat> {
+	let mut image_f32 = Mat::default();
+	image.convert_to(&mut image_f32, CV_32F, 1.0 / 255.0, 0.0)?;
+
+	let mut lab = Mat::default();
+	imgproc::cvt_color(
+		&image_f32,
+		&mut lab,
+		imgproc::COLOR_BGR2Lab,
+		0,
+		core::AlgorithmHint::ALGO_HINT_DEFAULT,
+	)?;
+	Ok(lab)
+}
+
+/// Converts an sRGB image with levels in `[0, 255]` to linear light in
+/// `[0, 1]`, as CV_32FC3.
+fn to_linear(image: &Mat) -> Result<Mat> {
+	let mut linear = Mat::default();
+	image.convert_to(&mut linear, CV_32F, 1.0, 0.0)?;
+	for y in 0..linear.rows() {
+		for pixel in linear.at_row_mut::<core::Vec3f>(y)? {
+			*pixel = core::Vec3f::from(pixel.0.map(srgb_to_linear));
+		}
+	}
+	Ok(linear)
+}
+
+/// Decodes an sRGB level in `[0, 255]` to linear light in `[0, 1]`.
+pub(crate) fn srgb_to_linear(c: f32) -> f32 {
+	let c = c / 255.0;
+	if c <= 0.04045 {
+		c / 12.92
+	} else {
+		((c + 0.055) / 1.055).powf(2.4)
+	}
+}
+
+pub(crate) fn linear_to_srgb(c: f64) -> f64 {
+	let c = c.clamp(0.0, 1.0);
+	let encoded = if c <= 0.0031308 {
+		c * 12.92
+	} else {
+		1.055 * c.powf(1.0 / 2.4) - 0.055
+	};
+	encoded * 255.0
+}
+
+fn lab_to_bgr(lab: [f64; 3]) -> Result<[f64; 3]> {
+	let pixel = Mat::new_rows_cols_with_default(
+		1,
+		1,
+		core::CV_32FC3,
+		Scalar::new(lab[0], lab[1], lab[2], 0.0),
+	)?;
+
+	let mut bgr = Mat::default();
+	imgproc::cvt_color(
+		&pixel,
+		&mut bgr,
+		imgproc::COLOR_Lab2BGR,
+		0,
+		core::AlgorithmHint::ALGO_HINT_DEFAULT,
+	)?;
+
+	let value = *bgr.at_2d::<core::Vec3f>(0, 0)?;
+	Ok(value.0.map(|c| c as f64 * 255.0))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::EdgeOperator;
+	use crate::output::render_linear;
+
+	/// Distance between two angles that count as the same when 180 degrees
+	/// apart.
+	fn folded_distance(a: f64, b: f64) -> f64 {
+		let d = (a - b).rem_euclid(180.0);
+		d.min(180.0 - d)
+	}
+
+	fn encode(ext: &str, img: &Mat) -> Result<Vec<u8>> {
+		let mut buf = core::Vector::<u8>::new();
+		assert!(imgcodecs::imencode(
+			ext,
+			img,
+			&mut buf,
+			&core::Vector::new()
+		)?);
+		Ok(buf.to_vec())
+	}
+
+	#[test]
+	fn roi_view_matches_its_copy() -> Result<()> {
+		// The ramp framed by a border, then shrunk back to it in place: the
+		// view keeps the frame's row step and is not continuous.
+		let ramp = render_linear(120, 80, 30.0, [255, 60, 0], [0, 40, 200])?;
+		let mut view = Mat::default();
+		core::copy_make_border(
+			&ramp,
+			&mut view,
+			10,
+			10,
+			20,
+			20,
+			core::BORDER_CONSTANT,
+			Scalar::all(255.0),
+		)?;
+		let view = view.adjust_roi(-10, -10, -20, -20)?;
+		assert!(!view.is_continuous());
+		assert_eq!(view.size()?, ramp.size()?);
+
+		let viewed = extract_gradient_from_mat(&view)?;
+		let copied = extract_gradient_from_mat(&view.try_clone()?)?;
+		assert_eq!(viewed.start_rgb, copied.start_rgb);
+		assert_eq!(viewed.end_rgb, copied.end_rgb);
+		assert_eq!(viewed.angle, copied.angle);
+		assert!(
+			(viewed.angle_folded() - 30.0).abs() < 2.0,
+			"angle {}",
+			viewed.angle
+		);
+		Ok(())
+	}
+
+	#[test]
+	fn single_channel_png() -> Result<()> {
+		let ramp = render_linear(128, 64, 90.0, [0, 0, 0], [255, 255, 255])?;
+		let mut gray = Mat::default();
+		imgproc::cvt_color(
+			&ramp,
+			&mut gray,
+			imgproc::COLOR_BGR2GRAY,
+			0,
+			core::AlgorithmHint::ALGO_HINT_DEFAULT,
+		)?;
+		let data = encode(".png", &gray)?;
+		assert_eq!(
+			decode_image(&data, &GradientConfig::default())?.channels(),
+			1
+		);
+
+		let result =
+			extract_gradient_hex_from_bytes_with_config(&data, &GradientConfig::default())?;
+		assert!(result.is_grayscale);
+		for [r, g, b] in [result.start_rgb, result.end_rgb] {
+			assert!(r == g && g == b, "{:?}", [r, g, b]);
+		}
+		assert!(
+			(result.angle_folded() - 90.0).abs() < 1.0,
+			"angle {}",
+			result.angle
+		);
+		Ok(())
+	}
+
+	#[cfg(feature = "image")]
+	#[test]
+	fn transparent_webp_keeps_its_alpha() -> Result<()> {
+		// Decoded by OpenCV or, without its WebP codec, by the `image` crate;
+		// either way the alpha has to survive.
+		let rgba = image::RgbaImage::from_fn(16, 8, |x, _| image::Rgba([255, 0, 0, x as u8 * 16]));
+		let mut data = Vec::new();
+		image::codecs::webp::WebPEncoder::new_lossless(&mut data).encode(
+			rgba.as_raw(),
+			16,
+			8,
+			image::ExtendedColorType::Rgba8,
+		)?;
+
+		let img = decode_image(&data, &GradientConfig::default())?;
+		assert_eq!(img.channels(), 4);
+		assert_eq!(
+			*img.at_2d::<core::Vec4b>(0, 3)?,
+			core::Vec4b::from([0, 0, 255, 48])
+		);
+		Ok(())
+	}
+
+	#[test]
+	fn transparent_border_does_not_bleed() -> Result<()> {
+		// A half-transparent red to blue ramp inside a fully transparent black
+		// frame; red plus blue stays 255 across the ramp.
+		let ramp = render_linear(120, 80, 90.0, [255, 0, 0], [0, 0, 255])?;
+		let mut bgra = Mat::default();
+		imgproc::cvt_color(
+			&ramp,
+			&mut bgra,
+			imgproc::COLOR_BGR2BGRA,
+			0,
+			core::AlgorithmHint::ALGO_HINT_DEFAULT,
+		)?;
+		for y in 0..bgra.rows() {
+			for pixel in bgra.at_row_mut::<core::Vec4b>(y)? {
+				pixel[3] = 200;
+			}
+		}
+		let mut framed = Mat::default();
+		core::copy_make_border(
+			&bgra,
+			&mut framed,
+			10,
+			10,
+			10,
+			10,
+			core::BORDER_CONSTANT,
+			Scalar::all(0.0),
+		)?;
+
+		let result = extract_gradient_from_mat(&framed)?;
+		for [r, g, b] in [result.start_rgb, result.end_rgb] {
+			assert!(
+				(253..=257).contains(&(r as u16 + b as u16)),
+				"{:?}",
+				[r, g, b]
+			);
+			assert!(g <= 1, "{:?}", [r, g, b]);
+		}
+		let [start, end] = [result.start_rgb, result.end_rgb];
+		assert!(
+			(start[0] > start[2]) != (end[0] > end[2]),
+			"{:?} {:?}",
+			start,
+			end
+		);
+		assert!(
+			(result.angle_folded() - 90.0).abs() < 2.0,
+			"angle {}",
+			result.angle
+		);
+		Ok(())
+	}
+
+	#[test]
+	fn wide_transparent_frame_keeps_the_end_bands() -> Result<()> {
+		// The frame is wider than `band_frac` of the whole image, so bands
+		// measured over it would be entirely transparent.
+		let ramp = render_linear(120, 80, 90.0, [255, 0, 0], [0, 0, 255])?;
+		let mut bgra = Mat::default();
+		imgproc::cvt_color(
+			&ramp,
+			&mut bgra,
+			imgproc::COLOR_BGR2BGRA,
+			0,
+			core::AlgorithmHint::ALGO_HINT_DEFAULT,
+		)?;
+		let mut framed = Mat::default();
+		core::copy_make_border(
+			&bgra,
+			&mut framed,
+			30,
+			30,
+			30,
+			30,
+			core::BORDER_CONSTANT,
+			Scalar::all(0.0),
+		)?;
+
+		let result = extract_gradient_from_mat(&framed)?;
+		assert!(result.start_band_px > 0 && result.end_band_px > 0);
+		// The wide blur pulls the bands a little towards the middle.
+		let red = |[r, g, b]: [u8; 3]| r >= 180 && g <= 1 && b <= 75;
+		let blue = |[r, g, b]: [u8; 3]| b >= 180 && g <= 1 && r <= 75;
+		let [start, end] = [result.start_rgb, result.end_rgb];
+		assert!(
+			(red(start) && blue(end)) || (blue(start) && red(end)),
+			"{:?} {:?}",
+			start,
+			end
+		);
+		Ok(())
+	}
+
+	#[test]
+	fn sobel_and_scharr_agree_off_axis() -> Result<()> {
+		let ramp = render_linear(100, 100, 30.0, [250, 200, 20], [20, 30, 120])?;
+		for edge_operator in [EdgeOperator::Sobel { ksize: 3 }, EdgeOperator::Scharr] {
+			let config = GradientConfig {
+				edge_operator,
+				..GradientConfig::default()
+			};
+			let result = extract_gradient_from_mat_with_config(&ramp, &config)?;
+			assert!(
+				(result.angle_folded() - 30.0).abs() < 2.0,
+				"{:?}: angle {}",
+				edge_operator,
+				result.angle
+			);
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn linearize_brightens_the_mix_of_white_and_black() -> Result<()> {
+		// Averaging the bytes of a white to black ramp gives mid gray; in
+		// linear light the white half weighs more.
+		let ramp = render_linear(100, 60, 90.0, [255, 255, 255], [0, 0, 0])?;
+		let naive = extract_gradient_from_mat(&ramp)?;
+		let config = GradientConfig {
+			linearize: true,
+			..GradientConfig::default()
+		};
+		let linear = extract_gradient_from_mat_with_config(&ramp, &config)?;
+		for c in 0..3 {
+			assert!(
+				linear.mid_rgb[c] >= naive.mid_rgb[c] + 8,
+				"{:?} vs {:?}",
+				linear.mid_rgb,
+				naive.mid_rgb
+			);
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn excluded_border_does_not_set_the_threshold() -> Result<()> {
+		// The black frame's edges are far stronger than the faint ramp; with
+		// them excluded, the threshold has to come from the ramp alone.
+		let ramp = render_linear(120, 120, 90.0, [200, 200, 200], [215, 215, 215])?;
+		let mut framed = Mat::default();
+		core::copy_make_border(
+			&ramp,
+			&mut framed,
+			12,
+			12,
+			12,
+			12,
+			core::BORDER_CONSTANT,
+			Scalar::all(0.0),
+		)?;
+		let config = GradientConfig {
+			blur_sigma: 1.0,
+			border_exclude_frac: 0.15,
+			..GradientConfig::default()
+		};
+
+		let result = extract_gradient_from_mat_with_config(&framed, &config)?;
+		assert_eq!(result.kind, GradientKind::Linear);
+		assert!(
+			(result.angle_folded() - 90.0).abs() < 2.0,
+			"angle {}",
+			result.angle
+		);
+		// The end bands are as wide as the excluded margin; they still have
+		// to land on the ramp instead of in the margin.
+		assert!(result.start_band_px > 0 && result.end_band_px > 0);
+		let (low, high) = if result.start_rgb[0] < result.end_rgb[0] {
+			(result.start_rgb, result.end_rgb)
+		} else {
+			(result.end_rgb, result.start_rgb)
+		};
+		for [r, g, b] in [low, high] {
+			assert!(r == g && g == b, "{:?}", [r, g, b]);
+		}
+		assert!((200..=206).contains(&low[0]), "{:?}", low);
+		assert!((209..=215).contains(&high[0]), "{:?}", high);
+		Ok(())
+	}
+
+	#[test]
+	fn apply_orientation_matches_exif() -> Result<()> {
+		let expected: [(u32, Vec<Vec<u8>>); 8] = [
+			(1, vec![vec![1, 2, 3], vec![4, 5, 6]]),
+			(2, vec![vec![3, 2, 1], vec![6, 5, 4]]),
+			(3, vec![vec![6, 5, 4], vec![3, 2, 1]]),
+			(4, vec![vec![4, 5, 6], vec![1, 2, 3]]),
+			(5, vec![vec![1, 4], vec![2, 5], vec![3, 6]]),
+			(6, vec![vec![4, 1], vec![5, 2], vec![6, 3]]),
+			(7, vec![vec![6, 3], vec![5, 2], vec![4, 1]]),
+			(8, vec![vec![3, 6], vec![2, 5], vec![1, 4]]),
+		];
+		for (orientation, rows) in expected {
+			let img = Mat::from_slice_2d(&[[1u8, 2, 3], [4, 5, 6]])?;
+			let oriented = apply_orientation(img, orientation)?;
+			assert_eq!(
+				oriented.to_vec_2d::<u8>()?,
+				rows,
+				"orientation {}",
+				orientation
+			);
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn exif_rotation_turns_the_angle() -> Result<()> {
+		// A horizontal ramp stored in a JPEG tagged with orientation 6, so it
+		// is displayed rotated a quarter turn clockwise.
+		let ramp = render_linear(160, 100, 90.0, [30, 30, 30], [220, 220, 220])?;
+		let jpeg = encode(".jpg", &ramp)?;
+		#[rustfmt::skip]
+		let app1: &[u8] = &[
+			0xFF, 0xE1, 0x00, 0x22, b'E', b'x', b'i', b'f', 0, 0,
+			b'M', b'M', 0x00, 0x2A, 0x00, 0x00, 0x00, 0x08,
+			0x00, 0x01,
+			0x01, 0x12, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x00, 0x06, 0x00, 0x00,
+			0x00, 0x00, 0x00, 0x00,
+		];
+		let data = [&jpeg[..2], app1, &jpeg[2..]].concat();
+		assert_eq!(exif_orientation(&data), 6);
+
+		let upright =
+			extract_gradient_hex_from_bytes_with_config(&data, &GradientConfig::default())?;
+		assert!(
+			folded_distance(upright.angle, 0.0) < 2.0,
+			"angle {}",
+			upright.angle
+		);
+		let config = GradientConfig {
+			respect_exif: false,
+			..GradientConfig::default()
+		};
+		let stored = extract_gradient_hex_from_bytes_with_config(&data, &config)?;
+		assert!(
+			folded_distance(stored.angle, 90.0) < 2.0,
+			"angle {}",
+			stored.angle
+		);
+		Ok(())
+	}
+
+	#[test]
+	fn single_pixel_is_flat() -> Result<()> {
+		let pixel = render_linear(1, 1, 0.0, [40, 120, 200], [40, 120, 200])?;
+		let result = extract_gradient_from_mat(&pixel)?;
+		assert_eq!(result.kind, GradientKind::Flat);
+		assert_eq!(result.angle, 0.0);
+		assert_eq!(result.start_rgb, [40, 120, 200]);
+		assert_eq!(result.end_rgb, [40, 120, 200]);
+		Ok(())
+	}
+
+	#[test]
+	fn one_pixel_wide_and_tall_strips() -> Result<()> {
+		let column = render_linear(1, 64, 180.0, [0, 0, 0], [255, 255, 255])?;
+		let result = extract_gradient_from_mat(&column)?;
+		assert_eq!(result.kind, GradientKind::Linear);
+		assert_eq!(result.angle_folded(), 0.0);
+
+		let row = render_linear(64, 1, 90.0, [0, 0, 0], [255, 255, 255])?;
+		let result = extract_gradient_from_mat(&row)?;
+		assert_eq!(result.angle_folded(), 90.0);
+
+		// Scaled to the fixed width this would be 100x1000000.
+		let long_column = render_linear(1, 10000, 180.0, [0, 0, 0], [255, 255, 255])?;
+		let result = extract_gradient_from_mat(&long_column)?;
+		assert_eq!(result.angle_folded(), 0.0);
+		Ok(())
+	}
+
+	#[test]
+	fn wide_banner_resizes_to_a_thin_strip() -> Result<()> {
+		let banner = render_linear(2000, 30, 90.0, [255, 94, 58], [41, 128, 185])?;
+		let config = GradientConfig {
+			edge_operator: EdgeOperator::Sobel { ksize: 5 },
+			..GradientConfig::default()
+		};
+		let result = extract_gradient_from_mat_with_config(&banner, &config)?;
+		assert_eq!(result.angle_folded(), 90.0);
+		Ok(())
+	}
+
+	#[test]
+	fn normalized_faint_ramp_keeps_its_angle() -> Result<()> {
+		let ramp = render_linear(120, 90, 30.0, [248, 248, 248], [255, 255, 255])?;
+		let plain = extract_gradient_from_mat(&ramp)?;
+		let config = GradientConfig {
+			normalize_contrast: true,
+			..GradientConfig::default()
+		};
+		let normalized = extract_gradient_from_mat_with_config(&ramp, &config)?;
+
+		assert!(
+			folded_distance(normalized.angle, 30.0) < 2.0,
+			"angle {}",
+			normalized.angle
+		);
+		// Only the angle path is stretched; the colors come from the image.
+		assert_eq!(
+			(normalized.start_rgb, normalized.end_rgb),
+			(plain.start_rgb, plain.end_rgb)
+		);
+		Ok(())
+	}
+
+	#[test]
+	fn solid_images_are_flat() -> Result<()> {
+		for color in [[128, 128, 128], [200, 30, 90]] {
+			let solid = render_linear(80, 60, 0.0, color, color)?;
+			let result = extract_gradient_from_mat(&solid)?;
+			assert_eq!(result.kind, GradientKind::Flat, "{:?}", color);
+			assert_eq!(result.confidence, 0.0, "{:?}", color);
+			assert_eq!((result.angle, result.raw_angle), (0.0, 0.0), "{:?}", color);
+			assert_eq!(result.start_rgb, color);
+			assert_eq!(result.end_rgb, color);
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn palette_limited_needs_a_few_colors() -> Result<()> {
+		// Six flat steps from black to white, like an indexed gradient.
+		let mut posterized = render_linear(120, 40, 90.0, [0, 0, 0], [255, 255, 255])?;
+		for y in 0..posterized.rows() {
+			for pixel in posterized.at_row_mut::<core::Vec3b>(y)? {
+				*pixel = core::Vec3b::all(pixel[0] / 51 * 51);
+			}
+		}
+		assert!(is_palette_limited(&posterized)?);
+
+		let smooth = render_linear(120, 40, 90.0, [0, 0, 0], [255, 255, 255])?;
+		assert!(!is_palette_limited(&smooth)?);
+
+		let mut two_color =
+			Mat::new_rows_cols_with_default(40, 120, core::CV_8UC3, Scalar::all(0.0))?;
+		let solid = two_color.try_clone()?;
+		assert!(!is_palette_limited(&solid)?);
+		imgproc::rectangle(
+			&mut two_color,
+			core::Rect::new(60, 0, 60, 40),
+			Scalar::all(255.0),
+			imgproc::FILLED,
+			imgproc::LINE_8,
+			0,
+		)?;
+		assert!(!is_palette_limited(&two_color)?);
+		Ok(())
+	}
+
+	#[test]
+	fn empty_band_has_no_sampled_color() -> Result<()> {
+		// The bands include the extreme pixels of the analysed region, so
+		// even a tiny `band_frac` samples something.
+		let ramp = render_linear(100, 60, 90.0, [255, 0, 0], [0, 0, 255])?;
+		let config = GradientConfig {
+			band_frac: 1e-6,
+			..GradientConfig::default()
+		};
+		let result = extract_gradient_from_mat_with_config(&ramp, &config)?;
+		assert!(result.sampled_start_rgb().is_some());
+		assert!(result.sampled_end_rgb().is_some());
+
+		// A mask that leaves nothing to analyse empties both bands, and the
+		// colors are only placeholders.
+		let mask = Mat::new_rows_cols_with_default(60, 100, core::CV_8UC1, Scalar::all(0.0))?;
+		let config = GradientConfig::default();
+		let analysis = analyze_masked(&ramp, Some(&mask), &config, &mut Scratch::default())?;
+		let result = gradient_from_analysis(&analysis, &config)?;
+		assert_eq!((result.start_band_px, result.end_band_px), (0, 0));
+		assert_eq!(result.sampled_start_rgb(), None);
+		assert_eq!(result.sampled_end_rgb(), None);
+		assert_eq!((result.start_rgb, result.end_rgb), ([0; 3], [0; 3]));
+		Ok(())
+	}
+
+	#[test]
+	fn mask_limits_the_projection_range() -> Result<()> {
+		// Only the columns 30 to 69 of a red to blue ramp count; its end
+		// bands are then columns 30 to 35 and 64 to 69.
+		let ramp = render_linear(100, 60, 90.0, [255, 0, 0], [0, 0, 255])?;
+		let mut mask = Mat::new_rows_cols_with_default(60, 100, core::CV_8UC1, Scalar::all(0.0))?;
+		imgproc::rectangle(
+			&mut mask,
+			core::Rect::new(30, 0, 40, 60),
+			Scalar::all(255.0),
+			imgproc::FILLED,
+			imgproc::LINE_8,
+			0,
+		)?;
+		let config = GradientConfig {
+			blur_sigma: 1.0,
+			..GradientConfig::default()
+		};
+		let analysis = analyze_masked(&ramp, Some(&mask), &config, &mut Scratch::default())?;
+		let result = gradient_from_analysis(&analysis, &config)?;
+
+		let (reddish, bluish) = ([171, 0, 84], [84, 0, 171]);
+		let [start, end] = [result.start_rgb, result.end_rgb];
+		assert!(
+			(close(start, reddish, 4) && close(end, bluish, 4))
+				|| (close(start, bluish, 4) && close(end, reddish, 4)),
+			"{:?} {:?}",
+			start,
+			end
+		);
+		Ok(())
+	}
+
+	#[test]
+	fn axis_aligned_ramps_give_exact_angles() -> Result<()> {
+		let horizontal = render_linear(120, 80, 90.0, [10, 40, 90], [240, 200, 120])?;
+		let result = extract_gradient_from_mat(&horizontal)?;
+		assert_eq!(result.angle, 90.0);
+		assert_eq!(crate::estimate_gradient_angle(&gray(&horizontal)?)?, 90.0);
+
+		let vertical = render_linear(120, 80, 180.0, [10, 40, 90], [240, 200, 120])?;
+		let result = extract_gradient_from_mat(&vertical)?;
+		assert!(
+			result.angle == 0.0 || result.angle == 180.0,
+			"angle {}",
+			result.angle
+		);
+		let estimated = crate::estimate_gradient_angle(&gray(&vertical)?)?;
+		assert!(
+			estimated == 0.0 || estimated == 180.0,
+			"angle {}",
+			estimated
+		);
+		Ok(())
+	}
+
+	fn luminance([r, g, b]: [u8; 3]) -> i32 {
+		(4899 * r as i32 + 9617 * g as i32 + 1868 * b as i32 + 8192) >> 14
+	}
+
+	fn close(a: [u8; 3], b: [u8; 3], tolerance: u8) -> bool {
+		a.iter().zip(&b).all(|(&a, &b)| a.abs_diff(b) <= tolerance)
+	}
+
+	fn gray(img: &Mat) -> Result<Mat> {
+		let mut gray = Mat::default();
+		gray_image(img, &mut gray)?;
+		Ok(gray)
+	}
+
+	proptest::proptest! {
+		#![proptest_config(proptest::prelude::ProptestConfig::with_cases(64))]
+
+		/// Any ramp drawn the way `render_to` draws it comes back with its
+		/// angle and colors, or their 180 degree flip with the colors swapped.
+		#[test]
+		fn recovers_rendered_ramps(
+			angle in 0.0..360.0f64,
+			start in proptest::array::uniform3(proptest::num::u8::ANY),
+			end in proptest::array::uniform3(proptest::num::u8::ANY),
+			landscape in proptest::bool::ANY,
+		) {
+			proptest::prop_assume!((luminance(start) - luminance(end)).abs() >= 64);
+			let (width, height) = if landscape { (128, 96) } else { (96, 128) };
+			let ramp = render_linear(width, height, angle, start, end).unwrap();
+			let config = GradientConfig {
+				resize_strategy: ResizeStrategy::FixedWidth(width),
+				blur_sigma: 1.0,
+				band_frac: 0.02,
+				..GradientConfig::default()
+			};
+
+			let result = extract_gradient_from_mat_with_config(&ramp, &config).unwrap();
+			let off = |target: f64| {
+				let d = (result.angle - target).rem_euclid(360.0);
+				d.min(360.0 - d)
+			};
+			let same = off(angle) < 2.0
+				&& close(result.start_rgb, start, 8)
+				&& close(result.end_rgb, end, 8);
+			let flipped = off(angle + 180.0) < 2.0
+				&& close(result.start_rgb, end, 8)
+				&& close(result.end_rgb, start, 8);
+			proptest::prop_assert!(
+				same || flipped,
+				"{:?} -> {:?} @ {} gave {} -> {} @ {}",
+				start,
+				end,
+				angle,
+				result.start_hex(),
+				result.end_hex(),
+				result.angle
+			);
+		}
+	}
+}
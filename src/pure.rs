@@ -0,0 +1,180 @@
+//! OpenCV-free version of the two-stop extraction, built on the `image` and
+//! `imageproc` crates. It mirrors the OpenCV pipeline but always uses a 3x3
+//! Sobel and the `image` crate's resampling and blur, so angles can differ by
+//! a few degrees and colors by a few levels.
+//!
+//! Its `GradientResult` is a reduced type, not the OpenCV one: it only has
+//! the end colors, their hex strings, the angle and the confidence. There is
+//! no `start_color`/`end_color`, `kind`, radial or conic info, and none of
+//! the diagnostics, so code meant to build with either backend should stick
+//! to those fields.
+
+use image::{GrayImage, RgbImage, imageops};
+use imageproc::{filter::gaussian_blur_f32, gradients};
+use std::path::Path;
+
+use crate::{EdgeOperator, GradientConfig, GradientError, ResizeStrategy, Result, compass_angle};
+
+/// The reduced result of the pure-Rust backend; see the module docs for
+/// what it lacks compared to the OpenCV `GradientResult`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GradientResult {
+	pub start_rgb: [u8; 3],
+	pub end_rgb: [u8; 3],
+	pub angle: f64,
+	pub confidence: f64,
+}
+
+impl GradientResult {
+	pub fn start_hex(&self) -> String {
+		rgb_to_hex(self.start_rgb)
+	}
+
+	pub fn end_hex(&self) -> String {
+		rgb_to_hex(self.end_rgb)
+	}
+}
+
+fn rgb_to_hex(rgb: [u8; 3]) -> String {
+	format!("#{:02x}{:02x}{:02x}", rgb[0], rgb[1], rgb[2])
+}
+
+pub fn extract_gradient_hex(
+	image_path: &Path,
+	resize_width: i32,
+	blur_sigma: f64,
+) -> Result<GradientResult> {
+	let config = GradientConfig {
+		resize_strategy: ResizeStrategy::FixedWidth(resize_width),
+		blur_sigma,
+		..GradientConfig::default()
+	};
+	extract_gradient_hex_with_config(image_path, &config)
+}
+
+pub fn extract_gradient_hex_with_config(
+	image_path: &Path,
+	config: &GradientConfig,
+) -> Result<GradientResult> {
+	// Only the 3x3 Sobel exists here, so the size checks run against it
+	// rather than the configured `edge_operator`.
+	GradientConfig {
+		edge_operator: EdgeOperator::Sobel { ksize: 3 },
+		..config.clone()
+	}
+	.validate()?;
+
+	let data = std::fs::read(image_path).map_err(|source| GradientError::ReadFailed {
+		path: image_path.to_path_buf(),
+		source,
+	})?;
+	if data.is_empty() {
+		return Err(GradientError::EmptyImage);
+	}
+
+	let img = image::load_from_memory(&data)?.to_rgb8();
+	extract_gradient(&img, config)
+}
+
+fn extract_gradient(img: &RgbImage, config: &GradientConfig) -> Result<GradientResult> {
+	let (width, height) = img.dimensions();
+	if width == 0 || height == 0 {
+		return Err(GradientError::EmptyImage);
+	}
+	let (small_width, small_height) = config
+		.resize_strategy
+		.target_size(width as f64, height as f64);
+	let small = imageops::thumbnail(img, small_width as u32, small_height as u32);
+	let blurred = if config.blur_sigma > 0.0 {
+		gaussian_blur_f32(&small, config.blur_sigma as f32)
+	} else {
+		small
+	};
+
+	let gray = imageops::grayscale(&blurred);
+	let (dominant_angle, confidence) = dominant_direction(&gray, config);
+
+	let dx = dominant_angle.cos();
+	let dy = dominant_angle.sin();
+	let project = |x: u32, y: u32| x as f64 * dx + y as f64 * dy;
+
+	let mut t_min = f64::INFINITY;
+	let mut t_max = f64::NEG_INFINITY;
+	for (x, y, _) in blurred.enumerate_pixels() {
+		let t = project(x, y);
+		t_min = t_min.min(t);
+		t_max = t_max.max(t);
+	}
+
+	let threshold_low = t_min + config.band_frac * (t_max - t_min);
+	let threshold_high = t_max - config.band_frac * (t_max - t_min);
+
+	Ok(GradientResult {
+		start_rgb: band_mean(&blurred, |x, y| project(x, y) <= threshold_low),
+		end_rgb: band_mean(&blurred, |x, y| project(x, y) >= threshold_high),
+		angle: compass_angle(dominant_angle),
+		confidence,
+	})
+}
+
+fn dominant_direction(gray: &GrayImage, config: &GradientConfig) -> (f64, f64) {
+	let grad_x = gradients::horizontal_sobel(gray);
+	let grad_y = gradients::vertical_sobel(gray);
+
+	let polar: Vec<(f64, f64)> = grad_x
+		.pixels()
+		.zip(grad_y.pixels())
+		.map(|(gx, gy)| {
+			let (gx, gy) = (gx[0] as f64, gy[0] as f64);
+			(gx.hypot(gy), gy.atan2(gx))
+		})
+		.collect();
+
+	let mut magnitudes: Vec<f64> = polar.iter().map(|&(mag, _)| mag).collect();
+	let threshold = config.magnitude_threshold.threshold(&mut magnitudes);
+	let valid = polar.iter().filter(|&&(mag, _)| mag > threshold);
+
+	if valid.clone().count() < 10 {
+		return (0.0, 0.0);
+	}
+
+	let mut sum_cos = 0.0;
+	let mut sum_sin = 0.0;
+	let mut sum_weight = 0.0;
+	for &(weight, a) in valid {
+		sum_cos += weight * (2.0 * a).cos();
+		sum_sin += weight * (2.0 * a).sin();
+		sum_weight += weight;
+	}
+
+	if sum_weight == 0.0 {
+		return (0.0, 0.0);
+	}
+
+	let avg_cos = sum_cos / sum_weight;
+	let avg_sin = sum_sin / sum_weight;
+	(
+		0.5 * avg_sin.atan2(avg_cos),
+		avg_cos.hypot(avg_sin).clamp(0.0, 1.0),
+	)
+}
+
+fn band_mean(image: &RgbImage, in_band: impl Fn(u32, u32) -> bool) -> [u8; 3] {
+	let mut sum = [0.0; 3];
+	let mut count = 0usize;
+	for (x, y, pixel) in image.enumerate_pixels() {
+		if in_band(x, y) {
+			for (total, &channel) in sum.iter_mut().zip(pixel.0.iter()) {
+				*total += channel as f64;
+			}
+			count += 1;
+		}
+	}
+
+	if count == 0 {
+		return [0, 0, 0];
+	}
+
+	sum.map(|total| (total / count as f64).clamp(0.0, 255.0).round() as u8)
+}
@@ -0,0 +1,79 @@
+use opencv::{
+	core::{CV_32F, Mat},
+	prelude::*,
+};
+use std::path::Path;
+
+use crate::angle::gray_image;
+use crate::pipeline::{Analysis, Scratch, analyze, gradient_from_analysis, read_image};
+use crate::{GradientConfig, GradientResult, Result};
+
+/// Steps between neighbouring profile bins below this many gray levels
+/// count as a plateau.
+const PLATEAU_STEP: f64 = 0.5;
+/// Steps of at least this many gray levels count as a jump between bands.
+const JUMP_STEP: f64 = 1.0;
+
+pub fn extract_with_quality(image_path: &Path) -> Result<(GradientResult, f64)> {
+	extract_with_quality_with_config(image_path, &GradientConfig::default())
+}
+
+/// Returns the gradient together with a banding score in `[0, 1]`. The
+/// unblurred, resized image is averaged into one-pixel bins along `t`; the
+/// score is the share of bin-to-bin steps that are flat plateaus, times the
+/// share of the total change carried by jumps. Smooth gradients score near
+/// 0, posterized ones with a few hard steps near 1.
+pub fn extract_with_quality_with_config(
+	image_path: &Path,
+	config: &GradientConfig,
+) -> Result<(GradientResult, f64)> {
+	let img = read_image(image_path, config)?;
+	let mut scratch = Scratch::default();
+	let analysis = analyze(&img, config, &mut scratch)?;
+	let result = gradient_from_analysis(&analysis, config)?;
+	let banding = banding_score(&analysis, &scratch.small)?;
+	Ok((result, banding))
+}
+
+fn banding_score(analysis: &Analysis, small: &Mat) -> Result<f64> {
+	let mut small_gray = Mat::default();
+	gray_image(small, &mut small_gray)?;
+	let mut gray = Mat::default();
+	small_gray.convert_to(&mut gray, CV_32F, 1.0, 0.0)?;
+
+	let bins = (analysis.t_max - analysis.t_min).ceil() as usize + 1;
+	let mut sums = vec![(0.0, 0usize); bins];
+	for y in 0..gray.rows() {
+		let gray_row = gray.at_row::<f32>(y)?;
+		let t_row = analysis.t.at_row::<f32>(y)?;
+		let opaque_row = match &analysis.opaque {
+			Some(opaque) => Some(opaque.at_row::<u8>(y)?),
+			None => None,
+		};
+
+		for (x, (&value, &t)) in gray_row.iter().zip(t_row).enumerate() {
+			if opaque_row.is_some_and(|row| row[x] == 0) {
+				continue;
+			}
+			let bin = ((t as f64 - analysis.t_min) as usize).min(bins - 1);
+			sums[bin].0 += value as f64;
+			sums[bin].1 += 1;
+		}
+	}
+
+	let profile: Vec<f64> = sums
+		.iter()
+		.filter(|&&(_, count)| count > 0)
+		.map(|&(sum, count)| sum / count as f64)
+		.collect();
+	let steps: Vec<f64> = profile.windows(2).map(|w| (w[1] - w[0]).abs()).collect();
+
+	let total: f64 = steps.iter().sum();
+	if steps.is_empty() || total < JUMP_STEP {
+		return Ok(0.0);
+	}
+
+	let plateaus = steps.iter().filter(|&&step| step < PLATEAU_STEP).count();
+	let jumps: f64 = steps.iter().filter(|&&step| step >= JUMP_STEP).sum();
+	Ok(plateaus as f64 / steps.len() as f64 * (jumps / total))
+}
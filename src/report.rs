@@ -0,0 +1,85 @@
+use std::path::Path;
+
+use crate::kind::{ConicInfo, GradientKind, RadialInfo};
+use crate::timing::{Timing, extract_with_timing_with_config};
+use crate::{GradientConfig, GradientResult, Result};
+
+/// Version of the `GradientReport` layout, bumped whenever a field changes
+/// meaning or is removed.
+pub const REPORT_VERSION: u32 = 1;
+
+/// Flat summary of a `GradientResult` and its diagnostics, with the colors
+/// as `#rrggbb` strings, for persisting as one payload. Diagnostics that
+/// were not enabled in the config are `None`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GradientReport {
+	pub version: u32,
+	pub start: String,
+	pub end: String,
+	pub mid: String,
+	pub angle: f64,
+	pub raw_angle: f64,
+	pub confidence: f64,
+	pub kind: GradientKind,
+	pub radial: Option<RadialInfo>,
+	pub conic: Option<ConicInfo>,
+	pub fit_error: Option<f64>,
+	pub start_band_px: i32,
+	pub end_band_px: i32,
+	pub multimodal: bool,
+	pub clamped: bool,
+	pub direction_ambiguous: bool,
+	pub is_grayscale: bool,
+	pub per_channel_angles: Option<[f64; 3]>,
+	pub palette_limited: bool,
+	pub timing: Option<Timing>,
+}
+
+impl GradientReport {
+	/// Cannot fail: the report holds only strings, numbers and flags, and
+	/// non-finite numbers are written as `null`.
+	#[cfg(feature = "serde")]
+	pub fn to_json(&self) -> String {
+		serde_json::to_string(self).expect("GradientReport always serializes")
+	}
+}
+
+impl From<&GradientResult> for GradientReport {
+	fn from(result: &GradientResult) -> Self {
+		Self {
+			version: REPORT_VERSION,
+			start: result.start_hex(),
+			end: result.end_hex(),
+			mid: result.midpoint_hex(),
+			angle: result.angle,
+			raw_angle: result.raw_angle,
+			confidence: result.confidence,
+			kind: result.kind,
+			radial: result.radial,
+			conic: result.conic,
+			fit_error: result.fit_error,
+			start_band_px: result.start_band_px,
+			end_band_px: result.end_band_px,
+			multimodal: result.multimodal,
+			clamped: result.clamped,
+			direction_ambiguous: result.direction_ambiguous,
+			is_grayscale: result.is_grayscale,
+			per_channel_angles: result.per_channel_angles,
+			palette_limited: result.palette_limited,
+			timing: None,
+		}
+	}
+}
+
+/// Extracts the gradient and reports it together with the stage timings.
+pub fn extract_gradient_report(
+	image_path: &Path,
+	config: &GradientConfig,
+) -> Result<GradientReport> {
+	let (result, timing) = extract_with_timing_with_config(image_path, config)?;
+	Ok(GradientReport {
+		timing: Some(timing),
+		..GradientReport::from(&result)
+	})
+}
@@ -0,0 +1,51 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::pipeline::{Scratch, analyze, gradient_from_analysis, read_image};
+use crate::{GradientConfig, GradientResult, Result};
+
+/// Wall-clock time spent in each stage of one extraction. `angle` covers
+/// the direction estimate and the projection onto it; `colors` the band
+/// sampling and everything after.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Timing {
+	pub decode: Duration,
+	pub resize: Duration,
+	pub blur: Duration,
+	pub sobel: Duration,
+	pub angle: Duration,
+	pub colors: Duration,
+}
+
+impl Timing {
+	pub fn total(&self) -> Duration {
+		self.decode + self.resize + self.blur + self.sobel + self.angle + self.colors
+	}
+}
+
+pub fn extract_with_timing(image_path: &Path) -> Result<(GradientResult, Timing)> {
+	extract_with_timing_with_config(image_path, &GradientConfig::default())
+}
+
+/// Like `extract_gradient_hex_with_config`, also returning where the time
+/// went. Reading the file counts towards `decode`.
+pub fn extract_with_timing_with_config(
+	image_path: &Path,
+	config: &GradientConfig,
+) -> Result<(GradientResult, Timing)> {
+	let start = Instant::now();
+	let img = read_image(image_path, config)?;
+	let decode = start.elapsed();
+
+	let mut scratch = Scratch::default();
+	let analysis = analyze(&img, config, &mut scratch)?;
+	let start = Instant::now();
+	let result = gradient_from_analysis(&analysis, config)?;
+	let timing = Timing {
+		decode,
+		colors: start.elapsed(),
+		..scratch.timing
+	};
+	Ok((result, timing))
+}
@@ -0,0 +1,33 @@
+//! Byte-exact results on the images under `tests/fixtures`. Extraction is
+//! meant to be deterministic with and without `rayon`, so run both
+//! `cargo test` and `cargo test --features rayon`. The fixtures are all
+//! 400x200, above the pixel count at which the loops go parallel.
+#![cfg(feature = "opencv")]
+
+use std::path::Path;
+
+use gradient_generator::{GradientConfig, ResizeStrategy, extract_gradient_hex_with_config};
+
+/// File, start hex, end hex and the angle to two decimals.
+const GOLDEN: [(&str, &str, &str, &str); 3] = [
+	("horizontal.png", "#ff5e3a", "#2980b9", "90.00"),
+	("horizontal_dark.png", "#14143c", "#fae68c", "90.00"),
+	("solid.png", "#8040c8", "#8040c8", "0.00"),
+];
+
+#[test]
+fn fixtures_match_golden_values() {
+	// The fixtures are 400 pixels wide, so nothing is resampled.
+	let config = GradientConfig {
+		resize_strategy: ResizeStrategy::FixedWidth(400),
+		..GradientConfig::default()
+	};
+	let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+
+	for (name, start, end, angle) in GOLDEN {
+		let result = extract_gradient_hex_with_config(&fixtures.join(name), &config).unwrap();
+		assert_eq!(result.start_hex(), start, "{}", name);
+		assert_eq!(result.end_hex(), end, "{}", name);
+		assert_eq!(format!("{:.2}", result.angle), angle, "{}", name);
+	}
+}